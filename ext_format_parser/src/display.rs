@@ -0,0 +1,377 @@
+use crate::parse::{
+    parse_condition, parse_group_modifiers, Align, FormatSpec, GroupModifier, Modifier, Order,
+    Pattern, QuoteToken,
+};
+use std::fmt::{self, Display, Formatter, Write};
+
+/// Turns tokens back into a template, which parses to the same tokens again.
+///
+/// Characters with a special meaning in templates are escaped, so the template
+/// can differ from the one the tokens were parsed from.
+pub fn to_template(tokens: &[QuoteToken]) -> String {
+    let mut res = String::new();
+    // A literal at the very start could be mistaken for a pragma
+    let escape_first = matches!(
+        tokens.first(),
+        Some(QuoteToken::Literal(literal)) if literal.trim_start().starts_with("#![")
+    );
+    write!(res, "{}", Tokens(tokens, escape_first)).unwrap();
+    res
+}
+
+/// A sequence of tokens, optionally escaping the first character of a leading literal.
+struct Tokens<'a>(&'a [QuoteToken], bool);
+
+impl Display for Tokens<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let Tokens(tokens, escape_first) = *self;
+        for (index, token) in tokens.iter().enumerate() {
+            let follows_group = index > 0 && matches!(tokens[index - 1], QuoteToken::Group(..));
+            match token {
+                // A literal directly after a group could be mistaken for its fallback
+                QuoteToken::Literal(literal) if follows_group && literal.starts_with('{') => {
+                    write_literal(f, literal, true)?
+                }
+                QuoteToken::Literal(literal) if index == 0 => {
+                    write_literal(f, literal, escape_first)?
+                }
+                token => write!(f, "{}", token)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a character so it is taken literally after unescaping and parsing.
+/// `n`, `r`, `t` and `x` can't be escaped, as they keep the backslash.
+fn write_escaped_char(f: &mut Formatter<'_>, ch: char) -> fmt::Result {
+    match ch {
+        '\\' => f.write_str(r"\\\\"),
+        ch => write!(f, r"\{}", ch),
+    }
+}
+
+fn write_literal(f: &mut Formatter<'_>, literal: &str, escape_first: bool) -> fmt::Result {
+    // Escaping any of the leading characters keeps the literal from being mistaken for modifiers
+    // or a condition, as `n`, `r`, `t` and `x` alone don't start any
+    let escaped_index = literal
+        .chars()
+        .position(|ch| !matches!(ch, 'n' | 'r' | 't' | 'x'))
+        .filter(|_| escape_first);
+    for (index, ch) in literal.chars().enumerate() {
+        match ch {
+            '\\' | '$' | '@' | '(' | ')' => write_escaped_char(f, ch)?,
+            ch if Some(index) == escaped_index => write_escaped_char(f, ch)?,
+            ch => f.write_char(ch)?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes a separator in parentheses, as in `$(...)(, )*`.
+fn write_separator(f: &mut Formatter<'_>, separator: &[QuoteToken]) -> fmt::Result {
+    f.write_char('(')?;
+    for token in separator {
+        match token {
+            QuoteToken::Literal(literal) => {
+                for ch in literal.chars() {
+                    match ch {
+                        '\\' | '$' | ')' => write_escaped_char(f, ch)?,
+                        ch => f.write_char(ch)?,
+                    }
+                }
+            }
+            token => write!(f, "{}", token)?,
+        }
+    }
+    f.write_char(')')
+}
+
+impl Display for QuoteToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            QuoteToken::Literal(literal) => write_literal(f, literal, false),
+            QuoteToken::Variable(ident, pattern, modifiers) => {
+                write!(f, "${{{}", ident)?;
+                if modifiers.contains(&Modifier::Optional) {
+                    f.write_char('?')?;
+                }
+                if let Some(pattern) = pattern {
+                    write!(f, ":{}", pattern)?;
+                }
+                for modifier in modifiers {
+                    if *modifier != Modifier::Optional {
+                        write!(f, "|{}", modifier)?;
+                    }
+                }
+                f.write_char('}')
+            }
+            QuoteToken::HiddenVariable(ident, pattern) => {
+                write!(f, "@{{{}", ident)?;
+                if let Some(pattern) = pattern {
+                    write!(f, ":{}", pattern)?;
+                }
+                f.write_char('}')
+            }
+            QuoteToken::Group(tokens, separator, fallback, modifiers) => {
+                f.write_char('$')?;
+                if modifiers.contains(&GroupModifier::IntoIter) {
+                    f.write_char('~')?;
+                }
+                f.write_char('(')?;
+                let last_separator = modifiers
+                    .iter()
+                    .find(|modifier| matches!(modifier, GroupModifier::LastSeparator(_)));
+                let modifiers: Vec<_> = modifiers
+                    .iter()
+                    .filter(|modifier| {
+                        !matches!(
+                            modifier,
+                            GroupModifier::IntoIter | GroupModifier::LastSeparator(_)
+                        )
+                    })
+                    .map(ToString::to_string)
+                    .collect();
+                let escape_first = match tokens.first() {
+                    // Whitespace after the modifiers is skipped
+                    Some(QuoteToken::Literal(literal)) if !modifiers.is_empty() => {
+                        literal.starts_with(char::is_whitespace)
+                    }
+                    // Without modifiers, the body could be mistaken for some or for a condition
+                    Some(QuoteToken::Literal(literal)) => {
+                        parse_group_modifiers(&mut literal.chars().peekable())
+                            .is_ok_and(|modifiers| !modifiers.is_empty())
+                            || !matches!(parse_condition(&mut literal.chars().peekable()), Ok(None))
+                    }
+                    _ => false,
+                };
+                if !modifiers.is_empty() {
+                    write!(f, "{}: ", modifiers.join(" "))?;
+                }
+                write!(f, "{})", Tokens(tokens, escape_first))?;
+                match separator.as_deref() {
+                    None => {}
+                    Some([QuoteToken::Literal(separator)])
+                        if separator.chars().count() == 1
+                            && separator != "("
+                            && separator != "*" =>
+                    {
+                        f.write_str(separator)?
+                    }
+                    Some(separator) => write_separator(f, separator)?,
+                }
+                if let Some(last_separator) = last_separator {
+                    write!(f, "{}", last_separator)?;
+                }
+                f.write_char('*')?;
+                if let Some(fallback) = fallback {
+                    f.write_str("{else:")?;
+                    for ch in fallback.chars() {
+                        match ch {
+                            '\\' | '}' => write_escaped_char(f, ch)?,
+                            ch => f.write_char(ch)?,
+                        }
+                    }
+                    f.write_char('}')?;
+                }
+                Ok(())
+            }
+            QuoteToken::Conditional(ident, negated, pattern, tokens) => {
+                let negation = if *negated { "!" } else { "" };
+                match pattern {
+                    Some(pattern) => write!(f, "$(if {}{{{}:{}}}: ", negation, ident, pattern)?,
+                    None => write!(f, "$(if {}{}: ", negation, ident)?,
+                }
+                // Whitespace after the condition is skipped
+                let escape_first = matches!(
+                    tokens.first(),
+                    Some(QuoteToken::Literal(literal)) if literal.starts_with(char::is_whitespace)
+                );
+                write!(f, "{})", Tokens(tokens, escape_first))
+            }
+        }
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Ident(ident) => f.write_str(ident),
+            Pattern::Tuple(patterns) => {
+                f.write_char('(')?;
+                for (index, pattern) in patterns.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", pattern)?;
+                }
+                f.write_char(')')
+            }
+        }
+    }
+}
+
+impl Display for Modifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Modifier::ZeroPad => f.write_str("zero_pad"),
+            Modifier::Cell(width) => write!(f, "cell={}", width),
+            Modifier::Hexdump(bytes_per_line) => write!(f, "hexdump={}", bytes_per_line),
+            Modifier::Box => f.write_str("box"),
+            Modifier::Bar(width, filled, empty) => write!(f, "bar({}, {}{})", width, filled, empty),
+            Modifier::Justify(width) => write!(f, "justify={}", width),
+            Modifier::Wrap(width, 0) => write!(f, "wrap={}", width),
+            Modifier::Wrap(width, hang) => write!(f, "wrap={},hang={}", width, hang),
+            Modifier::Regex => f.write_str("regex"),
+            Modifier::Bit => f.write_str("bit"),
+            Modifier::Dsv(',') => f.write_str("csv"),
+            Modifier::Dsv(delimiter) => write!(f, "dsv(\"{}\")", delimiter),
+            Modifier::Money(currency) => write!(f, "money(\"{}\")", currency),
+            Modifier::Diff(marker) => write!(f, "diff(\"{}\")", marker),
+            Modifier::Raw => f.write_str("raw"),
+            Modifier::DurationAuto => f.write_str("duration_auto"),
+            Modifier::Filter(path) => write!(f, "filter({})", path),
+            Modifier::Optional => f.write_char('?'),
+            Modifier::Format(spec) => write!(f, "{}", spec),
+        }
+    }
+}
+
+impl Display for FormatSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(fill) = self.fill {
+            f.write_char(fill)?;
+        }
+        match self.align {
+            Some(Align::Left) => f.write_char('<')?,
+            Some(Align::Center) => f.write_char('^')?,
+            Some(Align::Right) => f.write_char('>')?,
+            None => {}
+        }
+        if self.zero {
+            f.write_char('0')?;
+        }
+        if let Some(width) = self.width {
+            write!(f, "{}", width)?;
+        }
+        if let Some(precision) = self.precision {
+            write!(f, ".{}", precision)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for GroupModifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            GroupModifier::SortByValue(Order::Ascending) => f.write_str("sort_by_value(asc)"),
+            GroupModifier::SortByValue(Order::Descending) => f.write_str("sort_by_value(desc)"),
+            GroupModifier::Rev => f.write_str("rev"),
+            GroupModifier::GroupBy(key) => write!(f, "group_by({})", key),
+            GroupModifier::SeparateBy(key) => write!(f, "separate_by({})", key),
+            GroupModifier::Transpose => f.write_str("transpose"),
+            GroupModifier::SkipEmpty => f.write_str("skip_empty"),
+            GroupModifier::Checkbox(selection) => write!(f, "checkbox({})", selection),
+            GroupModifier::Radio(selection) => write!(f, "radio({})", selection),
+            // Written as sigil in front of the group, as in `$~(...)*`
+            GroupModifier::IntoIter => f.write_char('~'),
+            // Written as second separator after the group, as in `$(...)(, )( and )*`
+            GroupModifier::LastSeparator(separator) => write_separator(f, separator),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_template;
+
+    fn assert_round_trip(template: &str) {
+        let tokens = parse_template(template).unwrap();
+        let printed = to_template(&tokens);
+        assert_eq!(
+            parse_template(&printed).unwrap(),
+            tokens,
+            "{} was printed as {}",
+            template,
+            printed
+        );
+    }
+
+    #[test]
+    fn test_to_template() {
+        let tokens = parse_template("Hello $name, $(${items|>4})(, )*!").unwrap();
+        assert_eq!(to_template(&tokens), "Hello ${name}, $(${items|>4})(, )*!");
+    }
+
+    #[test]
+    fn test_round_trip_variables() {
+        assert_round_trip("Hello, $name!");
+        assert_round_trip("$first$second");
+        assert_round_trip("${name:inner}");
+        assert_round_trip("@{map:(key, (first, second))}$key");
+        assert_round_trip("${value?|>8}");
+        assert_round_trip("${value?:inner|zero_pad}");
+        assert_round_trip(
+            "${value|cell=8|hexdump=4|box|justify=20|regex|filter(shout)|filter(text::snake)}",
+        );
+        assert_round_trip("${nanos|duration_auto|>8}");
+        assert_round_trip("${amount|money(\"$\")|>12} ${amount|money(\"\")}");
+        assert_round_trip("${old|diff(\"-\")}${new|diff(\"+\")}${same|diff(\" \")}");
+        assert_round_trip("${flag|bit} ${flag|bit|>3}");
+        assert_round_trip("${text|wrap=72} ${text|wrap=72,hang=4|box}");
+        assert_round_trip("${field|csv} ${field|dsv(\"\t\")} ${field|dsv(\";\")}");
+        assert_round_trip("${html?|raw}");
+        assert_round_trip("${ratio|bar=10} ${ratio|bar(10, =.)}");
+        assert_round_trip("${value|*^12} ${value|<} ${value|08} ${value|0}");
+        assert_round_trip("${value|.2} ${value|.>8.3} ${value|08.1}");
+        assert_round_trip("@{hidden:(a, b)}");
+        assert_round_trip("$(@{3}line)*");
+        assert_round_trip("$(@{3:i}$i)*");
+    }
+
+    #[test]
+    fn test_round_trip_groups() {
+        assert_round_trip("$($items)*");
+        assert_round_trip("$($items),*");
+        assert_round_trip("$($items)(, and )*");
+        assert_round_trip("$($items)($separator)*");
+        assert_round_trip("$($items)(*)*");
+        assert_round_trip("$($items)(()*");
+        assert_round_trip("$($items))*");
+        assert_round_trip("$(@rows $($rows)(|)*)(\n)*");
+        assert_round_trip("$($items)*{else:nothing \\} here}");
+        assert_round_trip("$(sort_by_value(desc) rev:  $items)*");
+        assert_round_trip("$(group_by(|(a, _)| a.len()): @{groups:(key, values)}$key)*");
+        assert_round_trip("$(separate_by(|n| n / 10): $numbers)(\n)*");
+        assert_round_trip("$(skip_empty rev: $(if flags: $names))(, )*");
+        assert_round_trip("$(transpose: $($rows)*)*");
+        assert_round_trip("$(checkbox(selected): $options)(\n)*");
+        assert_round_trip("$(radio(|option| option.len() > 3): $options)(\n)*");
+        assert_round_trip("$~(rev: $items)*");
+        assert_round_trip("$~($items)*");
+        assert_round_trip("$($items)(, )( and )*");
+        assert_round_trip("$~(rev: $items),($#last)*{else:none}");
+        assert_round_trip("$(if verbose: debug $info)");
+        assert_round_trip("$(if !verbose: quiet)$($(if !#first: , )$items)*");
+        assert_round_trip("$(if {user:(name, _)}:\n $name)$(if a:$(if b: both))");
+        assert_round_trip("$($(if {opt:value}: $value)$items)(, )*");
+    }
+
+    #[test]
+    fn test_round_trip_escapes() {
+        assert_round_trip("\\$ \\@ @@ \\( \\) \\\\\\\\ \\\\n");
+        assert_round_trip("\\n\\t\\x41\\xc3\\xa9");
+        assert_round_trip("$(\\(a\\)b (c))*");
+        assert_round_trip("$(rev\\: $items)*");
+        assert_round_trip("$(tr\\anspose: $items)*");
+        assert_round_trip("$(\\\\n$items)(\\\\t)*");
+        assert_round_trip("$(rev x: $items)*");
+        assert_round_trip("$(if verbose\\: $items)*");
+        assert_round_trip("$(\\n$items)*");
+        assert_round_trip("$($items)*\\{else:}");
+        assert_round_trip("$($items)(\\\\\\\\)*");
+        assert_round_trip("$($items)(\\) \\$ \\\\\\\\ \\n)*");
+        assert_round_trip("\\#![on_missing=error]");
+    }
+}
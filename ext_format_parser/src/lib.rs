@@ -0,0 +1,61 @@
+//! # ext_format_parser
+//!
+//! The template parser behind the [`ext_format`](https://crates.io/crates/ext_format) macros.
+//!
+//! Proc-macro crates can only export macros, so the parsing logic lives in this crate and
+//! `ext_format` depends on it. The parser turns a template into a list of [`QuoteToken`]s,
+//! which can be inspected or turned back into a template with [`to_template`]:
+//!
+//! ```rust
+//! use ext_format_parser::{parse_template, to_template, QuoteToken};
+//!
+//! let tokens = parse_template("Hello, $($names)(, )*!").unwrap();
+//! assert_eq!(tokens[0], QuoteToken::Literal("Hello, ".to_string()));
+//! assert!(matches!(tokens[1], QuoteToken::Group(..)));
+//!
+//! assert_eq!(parse_template(&to_template(&tokens)).unwrap(), tokens);
+//! ```
+//!
+//! Invalid templates are reported as [`ParseError`]:
+//!
+//! ```rust
+//! use ext_format_parser::parse_template;
+//!
+//! let error = parse_template("${name|cell}").unwrap_err();
+//! assert_eq!(error.to_string(), "cell expects a number");
+//! ```
+
+mod display;
+mod parse;
+mod util;
+
+pub use crate::display::to_template;
+pub use crate::parse::{
+    parse_pragmas, Align, Escape, FormatSpec, GroupModifier, Modifier, OnMissing, Order,
+    ParseError, Pattern, Pragmas, QuoteToken,
+};
+pub use crate::util::{unescape, unindent, unindent_with_tab_width};
+
+/// Parses a template like the ones passed to `ext_format!`.
+///
+/// Escape sequences are resolved first and pragmas at the start of the template are skipped.
+pub fn parse_template(source: &str) -> Result<Vec<QuoteToken>, ParseError> {
+    parse_template_with_pragmas(source).map(|(_, tokens)| tokens)
+}
+
+/// Parses a template like [`parse_template`] and also returns its pragmas.
+pub fn parse_template_with_pragmas(source: &str) -> Result<(Pragmas, Vec<QuoteToken>), ParseError> {
+    parse_template_with_sigil(source, '$')
+}
+
+/// Parses a template like [`parse_template_with_pragmas`], in which interpolations start with
+/// `sigil` instead of `$`, e.g. `%name`.
+pub fn parse_template_with_sigil(
+    source: &str,
+    sigil: char,
+) -> Result<(Pragmas, Vec<QuoteToken>), ParseError> {
+    let unescaped_source = unescape(source)?;
+    let (pragmas, unescaped_source) = parse_pragmas(&unescaped_source)?;
+    let tokens = parse::parse(unescaped_source, sigil)?;
+    Ok((pragmas, tokens))
+}
@@ -0,0 +1,2279 @@
+use core::iter::Peekable;
+use core::str::Chars;
+use std::fmt;
+
+/// A token of a parsed template.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuoteToken {
+    /// Text which is output as is.
+    Literal(String),
+    /// A variable like `$name` or `${name:inner|modifier}`.
+    Variable(String, Option<Pattern>, Vec<Modifier>),
+    /// A variable like `@name`, which is iterated by a group but not output.
+    HiddenVariable(String, Option<Pattern>),
+    /// A repetition like `$(...)sep*` with its body, separator, fallback and modifiers.
+    Group(
+        Vec<QuoteToken>,
+        Option<Vec<QuoteToken>>,
+        Option<String>,
+        Vec<GroupModifier>,
+    ),
+    /// A conditional like `$(if flag: ...)` with the `bool` or `Option` it tests, whether the
+    /// test is negated like in `$(if !flag: ...)`, the pattern the value of an `Option` is bound
+    /// to and its body.
+    Conditional(String, bool, Option<Pattern>, Vec<QuoteToken>),
+}
+
+/// The name a variable is bound to, e.g. `inner` in `{var:inner}`.
+/// Tuples like `{map:(key, value)}` destructure the variable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Ident(String),
+    Tuple(Vec<Pattern>),
+}
+
+/// Modifiers changing how a variable is rendered, e.g. `zero_pad` in `${var|zero_pad}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Modifier {
+    /// Zero-pads numbers to the width of the widest element of the repetition.
+    ZeroPad,
+    /// Truncates and pads values to the width of the widest element of the repetition,
+    /// but at most to the given number of characters.
+    Cell(usize),
+    /// Renders bytes as hexdump with the given number of bytes per line.
+    Hexdump(usize),
+    /// Surrounds the value with a box-drawing frame sized to its widest line.
+    Box,
+    /// Renders a ratio from 0.0 to 1.0 as a progress bar of the given width,
+    /// using the given characters for the filled and the empty part.
+    Bar(usize, char, char),
+    /// Wraps text to the given width and stretches all lines but the last to exactly that width.
+    Justify(usize),
+    /// Wraps text to the given width, indenting continuation lines by the given number of spaces.
+    Wrap(usize, usize),
+    /// Escapes regex metacharacters, so the value matches literally inside a regex.
+    Regex,
+    /// Quotes the value as a field of delimiter-separated values, e.g. CSV for `,`.
+    Dsv(char),
+    /// Renders a number with two decimals and thousands separators, prefixed by a currency symbol.
+    Money(String),
+    /// Prefixes each line of the value with the given diff marker, e.g. `+`.
+    Diff(String),
+    /// Renders a `bool` as `1` or `0`.
+    Bit,
+    /// Renders a count of nanoseconds with the unit fitting its magnitude, e.g. `1.5ms`.
+    DurationAuto,
+    /// Inserts the value as is, even if the template escapes values with `#![escape=html]`.
+    Raw,
+    /// Passes the rendered value through a filter registered with `ext_format_filter!`.
+    Filter(String),
+    /// Renders the value of an `Option` or `Result`, written as `${name?}`.
+    Optional,
+    /// Pads values like Rust's format specs, e.g. `>8` or `0>3`.
+    Format(FormatSpec),
+}
+
+/// A subset of Rust's format spec: `[[fill]align][0][width][.precision]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FormatSpec {
+    pub fill: Option<char>,
+    pub align: Option<Align>,
+    pub zero: bool,
+    pub width: Option<usize>,
+    pub precision: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// Modifiers changing how a group iterates, e.g. `sort_by_value` in `$(sort_by_value: ...)*`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GroupModifier {
+    SortByValue(Order),
+    /// Iterates the elements in reverse order.
+    Rev,
+    /// Partitions the elements by the key returned by the given closure and iterates
+    /// `(key, elements)` pairs in the order the keys first appear.
+    GroupBy(String),
+    /// Only emits the separator between consecutive elements for which the given closure
+    /// returns different keys.
+    SeparateBy(String),
+    /// Renders each element up front and only emits the separator between non-empty ones.
+    SkipEmpty,
+    /// Iterates the columns of nested collections instead of their rows,
+    /// stopping at the shortest row.
+    Transpose,
+    /// Prefixes each element with `[x] ` if it is selected and `[ ] ` otherwise. The selection
+    /// is either the index of the selected element or a closure testing each element.
+    Checkbox(String),
+    /// Like [`GroupModifier::Checkbox`], but with the markers `(*) ` and `( ) `.
+    Radio(String),
+    /// Consumes the variables with `into_iter()` instead of borrowing them, written as `$~(...)*`.
+    IntoIter,
+    /// The separator between the last two elements, written as a second separator after the
+    /// first one, as in `$(...)(, )( and )*`.
+    LastSeparator(Vec<QuoteToken>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
+/// Settings for a whole template, given as `#![name=value]` at its start.
+#[derive(Debug, Default, PartialEq)]
+pub struct Pragmas {
+    pub on_missing: OnMissing,
+    pub escape: Escape,
+    /// Whether the output ends with exactly one line break, given as `#![final_newline]`.
+    pub final_newline: bool,
+    /// Whether lists with a last separator keep the separator before it, as in `a, b, and c`,
+    /// given as `#![oxford_comma]`.
+    pub oxford_comma: bool,
+    /// The number of decimals of floats without a precision of their own, given as `#![precision=2]`.
+    pub precision: Option<usize>,
+}
+
+/// How `${name?}` renders a missing value.
+#[derive(Debug, Default, PartialEq)]
+pub enum OnMissing {
+    #[default]
+    Empty,
+    Error,
+}
+
+/// How values are escaped before they are inserted into the output.
+#[derive(Debug, Default, PartialEq)]
+pub enum Escape {
+    #[default]
+    None,
+    /// Escapes the characters with a special meaning in HTML, i.e. `&`, `<`, `>`, `"` and `'`.
+    Html,
+}
+
+/// An error in the syntax of a template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    message: String,
+}
+
+impl ParseError {
+    pub(crate) fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+type ParseResult<T> = Result<T, ParseError>;
+
+macro_rules! parse_error {
+    ($($arg:tt)*) => {
+        ParseError::new(format!($($arg)*))
+    };
+}
+
+/// Parses the pragmas at the start of a template and returns them together with the rest of it.
+/// Whitespace before the pragmas and the line break after the last one are removed as well.
+pub fn parse_pragmas(source: &str) -> ParseResult<(Pragmas, &str)> {
+    let mut pragmas = Pragmas::default();
+    let mut rest = source;
+    while let Some(pragma) = rest.trim_start().strip_prefix("#![") {
+        let (pragma, remainder) = pragma
+            .split_once(']')
+            .ok_or_else(|| parse_error!("expected ]"))?;
+        parse_pragma(&mut pragmas, pragma.trim())?;
+        rest = remainder;
+    }
+    if rest.len() != source.len() {
+        rest = rest
+            .strip_prefix("\r\n")
+            .or_else(|| rest.strip_prefix('\n'))
+            .unwrap_or(rest);
+    }
+    Ok((pragmas, rest))
+}
+
+fn parse_pragma(pragmas: &mut Pragmas, pragma: &str) -> ParseResult<()> {
+    let (name, value) = match pragma.split_once('=') {
+        Some((name, value)) => (name.trim(), Some(value.trim())),
+        None => (pragma, None),
+    };
+    match name {
+        "on_missing" => {
+            pragmas.on_missing = match value {
+                Some("empty") => OnMissing::Empty,
+                Some("error") => OnMissing::Error,
+                _ => return Err(parse_error!("on_missing expects empty or error")),
+            }
+        }
+        "escape" => {
+            pragmas.escape = match value {
+                Some("none") => Escape::None,
+                Some("html") => Escape::Html,
+                _ => return Err(parse_error!("escape expects none or html")),
+            }
+        }
+        "final_newline" => {
+            if value.is_some() {
+                return Err(parse_error!("final_newline does not take a value"));
+            }
+            pragmas.final_newline = true;
+        }
+        "oxford_comma" => {
+            if value.is_some() {
+                return Err(parse_error!("oxford_comma does not take a value"));
+            }
+            pragmas.oxford_comma = true;
+        }
+        "precision" => {
+            let precision = value
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| parse_error!("precision expects a number"))?;
+            pragmas.precision = Some(precision);
+        }
+        _ => return Err(parse_error!("unknown pragma {}", name)),
+    }
+    Ok(())
+}
+
+/// A simple recursive descent parser
+/// It is quite fast but definitely needs a bit of a refactoring before release
+/// I will probably end up writing a library to do this eventually
+/// Interpolations start with `sigil`, which is `$` unless the macro chooses another one.
+pub(crate) fn parse(source: &str, sigil: char) -> ParseResult<Vec<QuoteToken>> {
+    parse_toplevel(&mut source.chars().peekable(), sigil)
+}
+
+macro_rules! flush_literal {
+    ($tokens:ident, $literal:ident) => {
+        if !$literal.is_empty() {
+            $tokens.push(QuoteToken::Literal($literal));
+            $literal = String::new();
+        }
+    };
+}
+
+macro_rules! final_flush_literal {
+    ($tokens:ident, $literal:ident) => {
+        if !$literal.is_empty() {
+            $tokens.push(QuoteToken::Literal($literal));
+        }
+    };
+}
+
+/// Reads the character escaped by a `\` into the literal. `\n`, `\r`, `\t` and `\x` are decoded
+/// by `unescape` before parsing, so here they were written as `\\n` and keep their backslash.
+fn parse_escaped_char(source: &mut Peekable<Chars>, literal: &mut String) -> ParseResult<()> {
+    let next_char = source
+        .next()
+        .ok_or_else(|| parse_error!("unexpected end after \\"))?;
+    if matches!(next_char, 'n' | 'r' | 't' | 'x') {
+        literal.push('\\');
+    }
+    literal.push(next_char);
+    Ok(())
+}
+
+fn parse_toplevel(source: &mut Peekable<Chars>, sigil: char) -> ParseResult<Vec<QuoteToken>> {
+    let mut res = vec![];
+
+    let mut current_literal = String::new();
+    while let Some(current_char) = source.next() {
+        match current_char {
+            '@' if source.peek() == Some(&'@') => {
+                source.next();
+                current_literal.push('@');
+            }
+            '@' => {
+                flush_literal!(res, current_literal);
+
+                let token = parse_hidden_variable(source)?;
+                res.push(token);
+            }
+            char if char == sigil => {
+                flush_literal!(res, current_literal);
+
+                let token = parse_binding(source, sigil)?;
+                res.push(token);
+            }
+            '\\' => parse_escaped_char(source, &mut current_literal)?,
+            char => {
+                current_literal.push(char);
+            }
+        }
+    }
+    final_flush_literal!(res, current_literal);
+
+    Ok(res)
+}
+
+fn parse_group(source: &mut Peekable<Chars>, sigil: char) -> ParseResult<QuoteToken> {
+    if source.next() != Some('(') {
+        return Err(parse_error!("expected ("));
+    }
+
+    if let Some((ident, negated, pattern)) = parse_condition(source)? {
+        let tokens = parse_group_body(source, sigil)?;
+        return Ok(QuoteToken::Conditional(ident, negated, pattern, tokens));
+    }
+
+    let mut modifiers = parse_group_modifiers(source)?;
+    let tokens = parse_group_body(source, sigil)?;
+    let (separator, last_separator) = parse_group_separator(source, sigil)?;
+    let fallback = parse_group_fallback(source)?;
+    if let Some(last_separator) = last_separator {
+        modifiers.push(GroupModifier::LastSeparator(last_separator));
+    }
+
+    Ok(QuoteToken::Group(tokens, separator, fallback, modifiers))
+}
+
+/// Parses the tokens of a group up to and including its closing parenthesis.
+fn parse_group_body(source: &mut Peekable<Chars>, sigil: char) -> ParseResult<Vec<QuoteToken>> {
+    let mut res = vec![];
+
+    let mut depth = 0;
+
+    let mut current_literal = String::new();
+    while let Some(current_char) = source.next() {
+        match current_char {
+            '@' if source.peek() == Some(&'@') => {
+                source.next();
+                current_literal.push('@');
+            }
+            '@' => {
+                flush_literal!(res, current_literal);
+
+                let token = parse_hidden_variable(source)?;
+                res.push(token);
+            }
+            char if char == sigil => {
+                flush_literal!(res, current_literal);
+
+                let token = parse_binding(source, sigil)?;
+                res.push(token);
+            }
+            '\\' => parse_escaped_char(source, &mut current_literal)?,
+            '(' => {
+                depth += 1;
+                current_literal.push('(');
+            }
+            ')' => {
+                if depth == 0 {
+                    final_flush_literal!(res, current_literal);
+                    return Ok(res);
+                } else {
+                    depth -= 1;
+                    current_literal.push(')');
+                }
+            }
+            char => {
+                current_literal.push(char);
+            }
+        }
+    }
+    Err(parse_error!("unexpected end of variable group"))
+}
+
+/// Parses the condition of a conditional like `if verbose:` or `if {option:value}:` at the
+/// start of a group. Whitespace after the `:` is skipped. If the group does not start with
+/// a condition, nothing is consumed.
+pub(crate) fn parse_condition(
+    source: &mut Peekable<Chars>,
+) -> ParseResult<Option<(String, bool, Option<Pattern>)>> {
+    let mut lookahead = source.clone();
+    if lookahead.next() != Some('i')
+        || lookahead.next() != Some('f')
+        || lookahead.next_if(|ch| *ch == ' ').is_none()
+    {
+        return Ok(None);
+    }
+    while lookahead.peek() == Some(&' ') {
+        lookahead.next();
+    }
+    let negated = lookahead.next_if_eq(&'!').is_some();
+    let Ok((ident, pattern, modifiers)) = parse_variable_idents(&mut lookahead) else {
+        return Ok(None);
+    };
+    while lookahead.peek() == Some(&' ') {
+        lookahead.next();
+    }
+    if lookahead.next() != Some(':') {
+        return Ok(None);
+    }
+    if !modifiers.is_empty() {
+        return Err(parse_error!("conditions can not have modifiers"));
+    }
+    if negated && pattern.is_some() {
+        return Err(parse_error!("negated conditions can not bind a pattern"));
+    }
+    while lookahead.peek().is_some_and(|ch| ch.is_whitespace()) {
+        lookahead.next();
+    }
+    *source = lookahead;
+    Ok(Some((ident, negated, pattern)))
+}
+
+/// The separator of a group and the separator between its last two elements.
+type Separators = (Option<Vec<QuoteToken>>, Option<Vec<QuoteToken>>);
+
+/// Parses the separators of a group up to and including the closing `*`.
+/// Separators are either a single character or a parenthesized string.
+/// Parenthesized separators can interpolate variables like `$sep`, evaluated at runtime,
+/// and escape characters like the group body, e.g. `\)`.
+fn parse_group_separator(source: &mut Peekable<Chars>, sigil: char) -> ParseResult<Separators> {
+    let next_char = source
+        .next()
+        .ok_or_else(|| parse_error!("expected separator"))?;
+    if next_char == '*' {
+        return Ok((None, None));
+    }
+    let separator = if next_char == '(' {
+        parse_separator_tokens(source, sigil)?
+    } else {
+        vec![QuoteToken::Literal(next_char.to_string())]
+    };
+    let last_separator = if source.next_if_eq(&'(').is_some() {
+        Some(parse_separator_tokens(source, sigil)?)
+    } else {
+        None
+    };
+    if source.next() != Some('*') {
+        return Err(parse_error!("expected * after variable group"));
+    }
+    Ok((Some(separator), last_separator))
+}
+
+/// Parses a separator in parentheses after the opening one, up to and including the closing one.
+fn parse_separator_tokens(
+    source: &mut Peekable<Chars>,
+    sigil: char,
+) -> ParseResult<Vec<QuoteToken>> {
+    let mut separator = vec![];
+    let mut current_literal = String::new();
+    while let Some(next_char) = source.next() {
+        match next_char {
+            ')' => break,
+            // A sigil which doesn't start a variable is part of the separator, like `$` in `( $ )`
+            char if char == sigil
+                && source
+                    .peek()
+                    .is_some_and(|ch| ch.is_alphabetic() || matches!(ch, '_' | '{' | '#')) =>
+            {
+                flush_literal!(separator, current_literal);
+                separator.push(parse_variable(source)?);
+            }
+            '\\' => parse_escaped_char(source, &mut current_literal)?,
+            char => current_literal.push(char),
+        }
+    }
+    final_flush_literal!(separator, current_literal);
+    Ok(separator)
+}
+
+/// Parses an optional list of modifiers like `sort_by_value(desc):` at the start of a group.
+/// Modifiers are separated by spaces and the list is terminated by a `:`. Whitespace after
+/// the `:` is skipped. If the group does not start with known modifiers, nothing is consumed.
+pub(crate) fn parse_group_modifiers(
+    source: &mut Peekable<Chars>,
+) -> ParseResult<Vec<GroupModifier>> {
+    let mut lookahead = source.clone();
+    let mut modifiers = vec![];
+    loop {
+        match lookahead.peek() {
+            Some(ch) if ch.is_alphabetic() || *ch == '_' => {}
+            _ => return Ok(vec![]),
+        }
+        let name = parse_ident(&mut lookahead)?;
+        let argument = if lookahead.peek() == Some(&'(') {
+            match parse_modifier_argument(&mut lookahead) {
+                Some(argument) => Some(argument),
+                None => return Ok(vec![]),
+            }
+        } else {
+            None
+        };
+        match to_group_modifier(&name, argument)? {
+            Some(modifier) => modifiers.push(modifier),
+            None => return Ok(vec![]),
+        }
+
+        while lookahead.peek() == Some(&' ') {
+            lookahead.next();
+        }
+        if lookahead.peek() == Some(&':') {
+            lookahead.next();
+            break;
+        }
+    }
+    while lookahead.peek().is_some_and(|ch| ch.is_whitespace()) {
+        lookahead.next();
+    }
+    *source = lookahead;
+    Ok(modifiers)
+}
+
+/// Reads the parenthesized argument of a modifier, e.g. `desc` in `sort_by_value(desc)`.
+/// Returns `None` if the parenthesis is never closed.
+fn parse_modifier_argument(source: &mut Peekable<Chars>) -> Option<String> {
+    if source.next() != Some('(') {
+        return None;
+    }
+    let mut argument = String::new();
+    let mut depth = 0;
+    for next_char in source.by_ref() {
+        match next_char {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(argument.trim().to_string()),
+            ')' => depth -= 1,
+            _ => {}
+        }
+        argument.push(next_char);
+    }
+    None
+}
+
+fn to_group_modifier(name: &str, argument: Option<String>) -> ParseResult<Option<GroupModifier>> {
+    let modifier = match name {
+        "sort_by_value" => GroupModifier::SortByValue(parse_order(argument)?),
+        "rev" => {
+            expect_no_argument(name, argument)?;
+            GroupModifier::Rev
+        }
+        "transpose" => {
+            expect_no_argument(name, argument)?;
+            GroupModifier::Transpose
+        }
+        "skip_empty" => {
+            expect_no_argument(name, argument)?;
+            GroupModifier::SkipEmpty
+        }
+        "group_by" => GroupModifier::GroupBy(
+            argument
+                .filter(|argument| !argument.is_empty())
+                .ok_or_else(|| parse_error!("group_by expects a key"))?,
+        ),
+        "separate_by" => GroupModifier::SeparateBy(
+            argument
+                .filter(|argument| !argument.is_empty())
+                .ok_or_else(|| parse_error!("separate_by expects a key"))?,
+        ),
+        "checkbox" | "radio" => {
+            let selection = argument
+                .filter(|argument| !argument.is_empty())
+                .ok_or_else(|| parse_error!("{} expects a selection", name))?;
+            if name == "checkbox" {
+                GroupModifier::Checkbox(selection)
+            } else {
+                GroupModifier::Radio(selection)
+            }
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(modifier))
+}
+
+fn parse_order(argument: Option<String>) -> ParseResult<Order> {
+    match argument.as_deref() {
+        None | Some("asc") => Ok(Order::Ascending),
+        Some("desc") => Ok(Order::Descending),
+        Some(_) => Err(parse_error!("expected asc or desc")),
+    }
+}
+
+/// Parses an optional `{else:...}` clause directly following a group.
+/// The fallback text is emitted instead of the group if it does not iterate at all.
+fn parse_group_fallback(source: &mut Peekable<Chars>) -> ParseResult<Option<String>> {
+    let mut lookahead = source.clone();
+    if !"{else:"
+        .chars()
+        .all(|expected| lookahead.next() == Some(expected))
+    {
+        return Ok(None);
+    }
+    *source = lookahead;
+
+    let mut fallback = String::new();
+    while let Some(next_char) = source.next() {
+        match next_char {
+            '\\' => parse_escaped_char(source, &mut fallback)?,
+            '}' => return Ok(Some(fallback)),
+            char => fallback.push(char),
+        }
+    }
+    Err(parse_error!("unexpected end of group fallback"))
+}
+
+fn parse_binding(source: &mut Peekable<Chars>, sigil: char) -> ParseResult<QuoteToken> {
+    match source.peek() {
+        Some('(') => parse_group(source, sigil),
+        Some('~') => {
+            source.next();
+            let mut group = parse_group(source, sigil)?;
+            if let QuoteToken::Group(_, _, _, ref mut modifiers) = group {
+                modifiers.insert(0, GroupModifier::IntoIter);
+            }
+            Ok(group)
+        }
+        _ => parse_variable(source),
+    }
+}
+
+fn parse_variable(source: &mut Peekable<Chars>) -> ParseResult<QuoteToken> {
+    let (ident, inner_ident, modifiers) = parse_variable_idents(source)?;
+    if let Some(Pattern::Tuple(_)) = inner_ident {
+        return Err(parse_error!("only hidden variables can be destructured"));
+    }
+    Ok(QuoteToken::Variable(ident, inner_ident, modifiers))
+}
+
+fn parse_hidden_variable(source: &mut Peekable<Chars>) -> ParseResult<QuoteToken> {
+    let mut lookahead = source.clone();
+    lookahead.next_if_eq(&'{');
+    if lookahead.peek().is_some_and(char::is_ascii_digit) {
+        return parse_count(source);
+    }
+    let (ident, inner_ident, modifiers) = parse_variable_idents(source)?;
+    if !modifiers.is_empty() {
+        return Err(parse_error!("hidden variables can not have modifiers"));
+    }
+    if ident.starts_with('#') {
+        return Err(parse_error!("{} can not be iterated", ident));
+    }
+    Ok(QuoteToken::HiddenVariable(ident, inner_ident))
+}
+
+/// Parses a count like `3` or `{3:i}`, which a group iterates like a collection of the numbers
+/// from 0 up to the count.
+fn parse_count(source: &mut Peekable<Chars>) -> ParseResult<QuoteToken> {
+    let braced = source.next_if_eq(&'{').is_some();
+    let count: String = std::iter::from_fn(|| source.next_if(char::is_ascii_digit)).collect();
+    count
+        .parse::<usize>()
+        .map_err(|_| parse_error!("invalid count {}", count))?;
+    if !braced {
+        return Ok(QuoteToken::HiddenVariable(count, None));
+    }
+    let inner_pattern = match source.next() {
+        Some(':') => Some(parse_pattern(source)?),
+        Some('}') => return Ok(QuoteToken::HiddenVariable(count, None)),
+        _ => return Err(parse_error!("expected : or }}")),
+    };
+    if source.next() != Some('}') {
+        return Err(parse_error!("expected }}"));
+    }
+    Ok(QuoteToken::HiddenVariable(count, inner_pattern))
+}
+
+fn parse_variable_idents(
+    source: &mut Peekable<Chars>,
+) -> ParseResult<(String, Option<Pattern>, Vec<Modifier>)> {
+    match source.peek() {
+        Some('{') => parse_bound_ident(source),
+        _ => Ok((parse_name(source)?, None, vec![])),
+    }
+}
+
+/// Parses the name of a variable, which is either an identifier or, with a leading `#`, one of
+/// the names reserved for the state of the enclosing repetitions like `#index`. The prefix keeps
+/// them from colliding with variables of the caller, as does `#pkg_version`.
+fn parse_name(source: &mut Peekable<Chars>) -> ParseResult<String> {
+    if source.next_if_eq(&'#').is_none() {
+        return parse_ident(source);
+    }
+    let name = parse_ident(source)?;
+    if !is_reserved_name(&name) {
+        return Err(parse_error!("unknown reserved name #{}", name));
+    }
+    Ok(format!("#{}", name))
+}
+
+fn is_reserved_name(name: &str) -> bool {
+    let is_level = |level: &str| level.chars().all(|ch| ch.is_ascii_digit());
+    matches!(
+        name,
+        "count" | "first" | "last" | "flatindex" | "pkg_version"
+    ) || name.strip_prefix("index").is_some_and(is_level)
+        || name.strip_prefix("number").is_some_and(is_level)
+}
+
+fn parse_ident(source: &mut Peekable<Chars>) -> ParseResult<String> {
+    let mut ident = String::new();
+    let var_start = source
+        .next_if(|ch| ch.is_alphabetic() || *ch == '_')
+        .ok_or_else(|| parse_error!("expected identifier"))?;
+    ident.push(var_start);
+    while let Some(current_char) = source.next_if(|ch| ch.is_alphanumeric() || *ch == '_') {
+        ident.push(current_char);
+    }
+    Ok(ident)
+}
+
+fn parse_bound_ident(
+    source: &mut Peekable<Chars>,
+) -> ParseResult<(String, Option<Pattern>, Vec<Modifier>)> {
+    if source.next() != Some('{') {
+        return Err(parse_error!("expected {{"));
+    }
+    let ident = parse_name(source)?;
+    let mut next_char = source.next().ok_or_else(|| parse_error!("expected }}"))?;
+
+    let mut modifiers = vec![];
+    if next_char == '?' {
+        modifiers.push(Modifier::Optional);
+        next_char = source.next().ok_or_else(|| parse_error!("expected }}"))?;
+    }
+
+    let inner_pattern = if next_char == ':' {
+        if ident.starts_with('#') {
+            return Err(parse_error!("{} can not be bound to a pattern", ident));
+        }
+        let inner_pattern = parse_pattern(source)?;
+        next_char = source.next().ok_or_else(|| parse_error!("expected }}"))?;
+        Some(inner_pattern)
+    } else {
+        None
+    };
+
+    while next_char == '|' {
+        modifiers.push(parse_modifier(source)?);
+        next_char = source.next().ok_or_else(|| parse_error!("expected }}"))?;
+    }
+
+    match next_char {
+        '}' => Ok((ident, inner_pattern, modifiers)),
+        _ if inner_pattern.is_some() => Err(parse_error!("expected }}")),
+        _ => Err(parse_error!("expected : or }}")),
+    }
+}
+
+/// Parses a single variable modifier, either as `name`, `name=value`, `name(value)`
+/// or as format spec like `>8`.
+fn parse_modifier(source: &mut Peekable<Chars>) -> ParseResult<Modifier> {
+    if !source
+        .peek()
+        .is_some_and(|ch| ch.is_alphabetic() || *ch == '_')
+    {
+        return Ok(Modifier::Format(parse_format_spec(source)?));
+    }
+    let name = parse_ident(source)?;
+    let argument = match source.peek() {
+        Some('=') => {
+            source.next();
+            let mut argument = String::new();
+            while let Some(next_char) = source.next_if(|ch| *ch != '|' && *ch != '}') {
+                argument.push(next_char);
+            }
+            Some(argument)
+        }
+        Some('(') => {
+            Some(parse_modifier_argument(source).ok_or_else(|| parse_error!("expected )"))?)
+        }
+        _ => None,
+    };
+    to_modifier(&name, argument)
+}
+
+fn parse_format_spec(source: &mut Peekable<Chars>) -> ParseResult<FormatSpec> {
+    let mut spec_source = String::new();
+    while let Some(next_char) = source.next_if(|ch| *ch != '|' && *ch != '}') {
+        spec_source.push(next_char);
+    }
+    if spec_source.is_empty() {
+        return Err(parse_error!("expected modifier"));
+    }
+
+    let to_align = |ch: char| match ch {
+        '<' => Some(Align::Left),
+        '^' => Some(Align::Center),
+        '>' => Some(Align::Right),
+        _ => None,
+    };
+
+    let mut spec = FormatSpec::default();
+    let mut chars = spec_source.chars().peekable();
+    let mut lookahead = chars.clone();
+    let first = lookahead.next();
+    if let Some(align) = lookahead.next().and_then(to_align) {
+        spec.fill = first;
+        spec.align = Some(align);
+        chars = lookahead;
+    } else if let Some(align) = first.and_then(to_align) {
+        spec.align = Some(align);
+        chars.next();
+    }
+    if let Some(fill @ ('{' | '}')) = spec.fill {
+        return Err(parse_error!("invalid fill character {}", fill));
+    }
+    spec.zero = chars.next_if_eq(&'0').is_some();
+
+    let invalid_spec = || parse_error!("invalid format spec {}", spec_source);
+    let width: String = std::iter::from_fn(|| chars.next_if(char::is_ascii_digit)).collect();
+    if !width.is_empty() {
+        spec.width = Some(width.parse().map_err(|_| invalid_spec())?);
+    }
+    if chars.next_if_eq(&'.').is_some() {
+        let precision: String = chars.by_ref().collect();
+        spec.precision = Some(precision.parse().map_err(|_| invalid_spec())?);
+    }
+    if chars.next().is_some() {
+        return Err(invalid_spec());
+    }
+    Ok(spec)
+}
+
+fn to_modifier(name: &str, argument: Option<String>) -> ParseResult<Modifier> {
+    let modifier = match name {
+        "zero_pad" => {
+            expect_no_argument(name, argument)?;
+            Modifier::ZeroPad
+        }
+        "cell" => Modifier::Cell(expect_number_argument(name, argument)?),
+        "hexdump" => {
+            let bytes_per_line = match argument {
+                Some(_) => expect_number_argument(name, argument)?,
+                None => 16,
+            };
+            if bytes_per_line == 0 {
+                return Err(parse_error!("hexdump expects at least one byte per line"));
+            }
+            Modifier::Hexdump(bytes_per_line)
+        }
+        "box" => {
+            expect_no_argument(name, argument)?;
+            Modifier::Box
+        }
+        "bar" => parse_bar_argument(argument)?,
+        "wrap" => parse_wrap_argument(argument)?,
+        "regex" => {
+            expect_no_argument(name, argument)?;
+            Modifier::Regex
+        }
+        "bit" => {
+            expect_no_argument(name, argument)?;
+            Modifier::Bit
+        }
+        "raw" => {
+            expect_no_argument(name, argument)?;
+            Modifier::Raw
+        }
+        "csv" => {
+            expect_no_argument(name, argument)?;
+            Modifier::Dsv(',')
+        }
+        "dsv" => parse_dsv_argument(argument)?,
+        "money" => Modifier::Money(unquote_argument(&argument.unwrap_or_default()).to_string()),
+        "diff" => {
+            let argument = argument.unwrap_or_default();
+            let marker = unquote_argument(&argument);
+            if marker.is_empty() {
+                return Err(parse_error!("diff expects a marker"));
+            }
+            Modifier::Diff(marker.to_string())
+        }
+        "duration_auto" => {
+            expect_no_argument(name, argument)?;
+            Modifier::DurationAuto
+        }
+        "justify" => {
+            let width = expect_number_argument(name, argument)?;
+            if width == 0 {
+                return Err(parse_error!(
+                    "justify expects a width of at least one column"
+                ));
+            }
+            Modifier::Justify(width)
+        }
+        "filter" => parse_filter_argument(argument)?,
+        // Any other name is a filter registered with `ext_format_filter!`, like `${val|shout}`
+        _ if argument.is_none() => Modifier::Filter(name.to_string()),
+        _ => return Err(parse_error!("unknown modifier {}", name)),
+    };
+    Ok(modifier)
+}
+
+/// Parses the argument of `filter(name)`, the name of a filter registered with
+/// `ext_format_filter!` or its path like `text::shout` if it was registered in another module.
+fn parse_filter_argument(argument: Option<String>) -> ParseResult<Modifier> {
+    let path = argument.unwrap_or_default();
+    let is_ident = |segment: &str| {
+        let mut chars = segment.chars();
+        segment != "_"
+            && chars
+                .next()
+                .is_some_and(|ch| ch.is_alphabetic() || ch == '_')
+            && chars.all(|ch| ch.is_alphanumeric() || ch == '_')
+    };
+    if !path.split("::").all(is_ident) {
+        return Err(parse_error!(
+            "filter expects the path of a filter, found {}",
+            path
+        ));
+    }
+    Ok(Modifier::Filter(path))
+}
+
+/// Parses the argument of `bar=N` or `bar(N, XY)`, where `X` and `Y` are the characters
+/// for the filled and the empty part of the bar.
+fn parse_bar_argument(argument: Option<String>) -> ParseResult<Modifier> {
+    let argument = argument.ok_or_else(|| parse_error!("bar expects a number"))?;
+    let (width, chars) = match argument.split_once(',') {
+        Some((width, chars)) => (width, chars.trim_start()),
+        None => (argument.as_str(), "#-"),
+    };
+    let width = expect_number_argument("bar", Some(width.to_string()))?;
+    match chars.chars().collect::<Vec<_>>()[..] {
+        [filled, empty] => Ok(Modifier::Bar(width, filled, empty)),
+        _ => Err(parse_error!(
+            "bar expects two characters for the filled and the empty part"
+        )),
+    }
+}
+
+/// Parses the argument of `wrap=N` or `wrap=N,hang=M`, where `M` is the indentation
+/// of continuation lines.
+fn parse_wrap_argument(argument: Option<String>) -> ParseResult<Modifier> {
+    let argument = argument.ok_or_else(|| parse_error!("wrap expects a number"))?;
+    let (width, hang) = match argument.split_once(',') {
+        Some((width, hang)) => {
+            let hang = hang
+                .trim()
+                .strip_prefix("hang")
+                .and_then(|hang| hang.trim_start().strip_prefix('='))
+                .ok_or_else(|| parse_error!("expected hang=N"))?;
+            (
+                width,
+                expect_number_argument("hang", Some(hang.to_string()))?,
+            )
+        }
+        None => (argument.as_str(), 0),
+    };
+    let width = expect_number_argument("wrap", Some(width.to_string()))?;
+    if hang >= width {
+        return Err(parse_error!("hang must be less than the width of wrap"));
+    }
+    Ok(Modifier::Wrap(width, hang))
+}
+
+/// Parses the delimiter of `dsv("X")`, which may also be written without quotes.
+fn parse_dsv_argument(argument: Option<String>) -> ParseResult<Modifier> {
+    let argument = argument.unwrap_or_default();
+    match unquote_argument(&argument).chars().collect::<Vec<_>>()[..] {
+        [delimiter] if !matches!(delimiter, '"' | '\n' | '\r') => Ok(Modifier::Dsv(delimiter)),
+        _ => Err(parse_error!("dsv expects a delimiter character")),
+    }
+}
+
+/// Strips the quotes around a string argument like `"$"`, if there are any.
+fn unquote_argument(argument: &str) -> &str {
+    argument
+        .strip_prefix('"')
+        .and_then(|argument| argument.strip_suffix('"'))
+        .unwrap_or(argument)
+}
+
+fn expect_number_argument(name: &str, argument: Option<String>) -> ParseResult<usize> {
+    argument
+        .and_then(|argument| argument.trim().parse().ok())
+        .ok_or_else(|| parse_error!("{} expects a number", name))
+}
+
+fn expect_no_argument(name: &str, argument: Option<String>) -> ParseResult<()> {
+    if argument.is_some() {
+        return Err(parse_error!("{} does not take an argument", name));
+    }
+    Ok(())
+}
+
+fn parse_pattern(source: &mut Peekable<Chars>) -> ParseResult<Pattern> {
+    if source.peek() != Some(&'(') {
+        return Ok(Pattern::Ident(parse_ident(source)?));
+    }
+    source.next();
+
+    let mut patterns = vec![];
+    loop {
+        while source.peek() == Some(&' ') {
+            source.next();
+        }
+        patterns.push(parse_pattern(source)?);
+        while source.peek() == Some(&' ') {
+            source.next();
+        }
+        match source.next() {
+            Some(',') => continue,
+            Some(')') => return Ok(Pattern::Tuple(patterns)),
+            _ => return Err(parse_error!("expected , or )")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QuoteToken::*;
+    use super::*;
+    use crate::util::{unescape, unindent};
+    use std::iter::Peekable;
+    use std::str::Chars;
+    macro_rules! expect_match {
+        ($value:expr => $pattern:pat in $unpacked:expr) => {
+            if let $pattern = $value {
+                $unpacked
+            } else {
+                panic!("unexpected enum Variant: {:?}", $value);
+            }
+        };
+    }
+    #[test]
+    fn test_parse_toplevel() {
+        let source = unescape(&unindent(
+            r#"
+        void $name($($types $names)(, )*) {
+            $func("hallo", $num);
+            $(@lines printf("$($lines)( --> )* %d, %d", $nums, $nums2))(;\n    )*;
+        }
+            "#,
+        ))
+        .unwrap();
+        let mut source: Peekable<Chars> = source.trim().chars().peekable();
+        let tokens = parse_toplevel(&mut source, '$').unwrap();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Literal("void ".to_string()),
+                Variable("name".to_string(), None, vec![]),
+                Literal("(".to_string()),
+                Group(
+                    vec![
+                        Variable("types".to_string(), None, vec![]),
+                        Literal(" ".to_string()),
+                        Variable("names".to_string(), None, vec![])
+                    ],
+                    Some(vec![Literal(", ".to_string())]),
+                    None,
+                    vec![]
+                ),
+                Literal(") {\n    ".to_string()),
+                Variable("func".to_string(), None, vec![]),
+                Literal("(\"hallo\", ".to_string()),
+                Variable("num".to_string(), None, vec![]),
+                Literal(");\n    ".to_string()),
+                Group(
+                    vec![
+                        HiddenVariable("lines".to_string(), None),
+                        Literal(" printf(\"".to_string()),
+                        Group(
+                            vec![Variable("lines".to_string(), None, vec![])],
+                            Some(vec![Literal(" --> ".to_string())]),
+                            None,
+                            vec![]
+                        ),
+                        Literal(" %d, %d\", ".to_string()),
+                        Variable("nums".to_string(), None, vec![]),
+                        Literal(", ".to_string()),
+                        Variable("nums2".to_string(), None, vec![]),
+                        Literal(")".to_string())
+                    ],
+                    Some(vec![Literal(";\n    ".to_string())]),
+                    None,
+                    vec![]
+                ),
+                Literal(";\n}".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_toplevel_matrix() {
+        let source = unescape(&unindent(
+            r#"
+        void func() {
+            $(@{matrix:inner_matrix}printf("$($inner_matrix) *");)(\n    )*
+            printf("\\(");
+        }
+            "#,
+        ))
+        .unwrap();
+        let mut source: Peekable<Chars> = source.trim().chars().peekable();
+        let tokens = parse_toplevel(&mut source, '$').unwrap();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Literal("void func() {\n    ".to_string()),
+                Group(
+                    vec![
+                        HiddenVariable(
+                            "matrix".to_string(),
+                            Some(Pattern::Ident("inner_matrix".to_string()))
+                        ),
+                        Literal("printf(\"".to_string()),
+                        Group(
+                            vec![Variable("inner_matrix".to_string(), None, vec![])],
+                            Some(vec![Literal(" ".to_string())]),
+                            None,
+                            vec![]
+                        ),
+                        Literal("\");".to_string())
+                    ],
+                    Some(vec![Literal("\n    ".to_string())]),
+                    None,
+                    vec![]
+                ),
+                Literal("\n    printf(\"(\");\n}".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_toplevel_escaped_at() {
+        let mut source: Peekable<Chars> = r"user\@example.com @@route".chars().peekable();
+        let tokens = parse_toplevel(&mut source, '$').unwrap();
+
+        assert_eq!(tokens, vec![Literal("user@example.com @route".to_string())]);
+    }
+
+    #[test]
+    fn test_parse_toplevel_with_custom_sigil() {
+        let mut source: Peekable<Chars> = r"$HOME %name %(%items)(%sep)* \%".chars().peekable();
+        let tokens = parse_toplevel(&mut source, '%').unwrap();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Literal("$HOME ".to_string()),
+                Variable("name".to_string(), None, vec![]),
+                Literal(" ".to_string()),
+                Group(
+                    vec![Variable("items".to_string(), None, vec![])],
+                    Some(vec![Variable("sep".to_string(), None, vec![])]),
+                    None,
+                    vec![]
+                ),
+                Literal(" %".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_group_with_escaped_at() {
+        let mut source: Peekable<Chars> = r"($user\@$domain @@)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, _, _, _) in {
+            assert_eq!(
+                tokens,
+                vec![
+                    Variable("user".to_string(), None, vec![]),
+                    Literal("@".to_string()),
+                    Variable("domain".to_string(), None, vec![]),
+                    Literal(" @".to_string())
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_parse_pragmas() {
+        let (pragmas, rest) = parse_pragmas("  #![on_missing=error]\nHello $name").unwrap();
+        assert_eq!(pragmas.on_missing, OnMissing::Error);
+        assert_eq!(rest, "Hello $name");
+
+        let (pragmas, rest) = parse_pragmas("\n#![on_missing = empty]\r\n\nHello").unwrap();
+        assert_eq!(pragmas, Pragmas::default());
+        assert_eq!(rest, "\nHello");
+    }
+
+    #[test]
+    fn test_parse_escape_pragma() {
+        let (pragmas, rest) = parse_pragmas("#![escape=html]\n#![on_missing=error]\n<p>").unwrap();
+        assert_eq!(pragmas.escape, Escape::Html);
+        assert_eq!(pragmas.on_missing, OnMissing::Error);
+        assert_eq!(rest, "<p>");
+    }
+
+    #[test]
+    fn test_parse_final_newline_pragma() {
+        let (pragmas, rest) = parse_pragmas("#![final_newline]\nfn main() {}").unwrap();
+        assert!(pragmas.final_newline);
+        assert_eq!(rest, "fn main() {}");
+    }
+
+    #[test]
+    #[should_panic(expected = "final_newline does not take a value")]
+    fn test_parse_final_newline_pragma_with_value() {
+        parse_pragmas("#![final_newline=true]").unwrap();
+    }
+
+    #[test]
+    fn test_parse_oxford_comma_pragma() {
+        let (pragmas, _) = parse_pragmas("#![oxford_comma]").unwrap();
+        assert!(pragmas.oxford_comma);
+    }
+
+    #[test]
+    fn test_parse_precision_pragma() {
+        let (pragmas, _) = parse_pragmas("#![precision=2]").unwrap();
+        assert_eq!(pragmas.precision, Some(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "precision expects a number")]
+    fn test_parse_invalid_precision_pragma() {
+        parse_pragmas("#![precision=two]").unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "escape expects none or html")]
+    fn test_parse_invalid_escape_pragma() {
+        parse_pragmas("#![escape=xml]").unwrap();
+    }
+
+    #[test]
+    fn test_parse_without_pragmas() {
+        let (pragmas, rest) = parse_pragmas("\n  Hello #![on_missing=error]").unwrap();
+        assert_eq!(pragmas, Pragmas::default());
+        assert_eq!(rest, "\n  Hello #![on_missing=error]");
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown pragma missing")]
+    fn test_parse_unknown_pragma() {
+        parse_pragmas("#![missing=empty]").unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "on_missing expects empty or error")]
+    fn test_parse_invalid_pragma_value() {
+        parse_pragmas("#![on_missing=skip]").unwrap();
+    }
+
+    #[test]
+    fn test_parse_optional_variable() {
+        let mut source: Peekable<Chars> = "{foo?:bar|>4}".chars().peekable();
+        let (ident, pattern, modifiers) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(ident, "foo");
+        assert_eq!(pattern, Some(Pattern::Ident("bar".to_string())));
+        assert_eq!(
+            modifiers,
+            vec![
+                Modifier::Optional,
+                Modifier::Format(FormatSpec {
+                    align: Some(Align::Right),
+                    width: Some(4),
+                    ..Default::default()
+                })
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_group_basic() {
+        let mut source: Peekable<Chars> = "(literal)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, separator, _, _) in {
+            assert_eq!(1, tokens.len());
+            expect_match!(&tokens[0] => QuoteToken::Literal(literal) in assert_eq!(literal, "literal"));
+            assert_eq!(separator, None);
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_char_separator() {
+        let mut source: Peekable<Chars> = "(literal);*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, separator, _, _) in {
+            assert_eq!(1, tokens.len());
+            expect_match!(&tokens[0] => QuoteToken::Literal(literal) in assert_eq!(literal, "literal"));
+            assert_eq!(separator, Some(vec![Literal(";".to_string())]));
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_string_separator() {
+        let mut source: Peekable<Chars> = "(literal)(=>)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, separator, _, _) in {
+            assert_eq!(1, tokens.len());
+            expect_match!(&tokens[0] => QuoteToken::Literal(literal) in assert_eq!(literal, "literal"));
+            assert_eq!(separator, Some(vec![Literal("=>".to_string())]));
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_last_separator() {
+        let mut source: Peekable<Chars> = "(literal)(, )( and $conjunction)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, separator, _, modifiers) in {
+            assert_eq!(separator, Some(vec![Literal(", ".to_string())]));
+            assert_eq!(
+                modifiers,
+                vec![GroupModifier::LastSeparator(vec![
+                    Literal(" and ".to_string()),
+                    Variable("conjunction".to_string(), None, vec![]),
+                ])]
+            );
+        });
+
+        let mut source: Peekable<Chars> = "(literal);(&)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, separator, _, modifiers) in {
+            assert_eq!(separator, Some(vec![Literal(";".to_string())]));
+            assert_eq!(
+                modifiers,
+                vec![GroupModifier::LastSeparator(vec![Literal("&".to_string())])]
+            );
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_escaped_separator() {
+        let mut source: Peekable<Chars> = "(literal)(\n)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, separator, _, _) in {
+            assert_eq!(1, tokens.len());
+            expect_match!(&tokens[0] => QuoteToken::Literal(literal) in assert_eq!(literal, "literal"));
+            assert_eq!(separator, Some(vec![Literal("\n".to_string())]));
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_escaped_escaped_separator() {
+        let mut source: Peekable<Chars> = "(literal)(\\n)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, separator, _, _) in {
+            assert_eq!(1, tokens.len());
+            expect_match!(&tokens[0] => QuoteToken::Literal(literal) in assert_eq!(literal, "literal"));
+            assert_eq!(separator, Some(vec![Literal("\\n".to_string())]));
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_escaped_parenthesis_separator() {
+        let mut source: Peekable<Chars> = r"($a)(\) => \$\\)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, separator, _, _) in {
+            assert_eq!(separator, Some(vec![Literal(r") => $\".to_string())]));
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_escaped_letter_separator() {
+        let mut source: Peekable<Chars> = r"($a)(,\t\x)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, separator, _, _) in {
+            assert_eq!(separator, Some(vec![Literal(r",\t\x".to_string())]));
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_variable_separator() {
+        let mut source: Peekable<Chars> = "(literal)( ${sep|>2} )*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, separator, _, _) in {
+            assert_eq!(
+                separator,
+                Some(vec![
+                    Literal(" ".to_string()),
+                    Variable(
+                        "sep".to_string(),
+                        None,
+                        vec![Modifier::Format(FormatSpec {
+                            align: Some(Align::Right),
+                            width: Some(2),
+                            ..Default::default()
+                        })]
+                    ),
+                    Literal(" ".to_string())
+                ])
+            );
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_sigil_in_separator() {
+        let mut source: Peekable<Chars> = "(literal)( $ )*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, separator, _, _) in {
+            assert_eq!(separator, Some(vec![Literal(" $ ".to_string())]));
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_fallback() {
+        let mut source: Peekable<Chars> = "(literal),*{else:(none)} rest".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, separator, fallback, _) in {
+            assert_eq!(1, tokens.len());
+            assert_eq!(separator, Some(vec![Literal(",".to_string())]));
+            assert_eq!(fallback, Some("(none)".to_string()));
+        });
+        assert_eq!(source.collect::<String>(), " rest");
+    }
+
+    #[test]
+    fn test_parse_group_with_escaped_fallback() {
+        let mut source: Peekable<Chars> = "(literal)*{else:{\\}}".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, _, fallback, _) in {
+            assert_eq!(fallback, Some("{}".to_string()));
+        });
+    }
+
+    #[test]
+    fn test_parse_group_without_fallback() {
+        let mut source: Peekable<Chars> = "(literal)*{elsewhere}".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, _, fallback, _) in {
+            assert_eq!(fallback, None);
+        });
+        assert_eq!(source.collect::<String>(), "{elsewhere}");
+    }
+
+    #[test]
+    fn test_parse_group_with_modifier() {
+        let mut source: Peekable<Chars> = "(sort_by_value(desc):  $var)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, _, _, modifiers) in {
+            assert_eq!(modifiers, vec![GroupModifier::SortByValue(Order::Descending)]);
+            assert_eq!(tokens, vec![Variable("var".to_string(), None, vec![])]);
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_multiple_modifiers() {
+        let mut source: Peekable<Chars> = "(sort_by_value rev: $var)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, _, _, modifiers) in {
+            assert_eq!(
+                modifiers,
+                vec![GroupModifier::SortByValue(Order::Ascending), GroupModifier::Rev]
+            );
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_group_by() {
+        let mut source: Peekable<Chars> =
+            "(group_by(|(key, _)| key.len()): $var)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, _, _, modifiers) in {
+            assert_eq!(
+                modifiers,
+                vec![GroupModifier::GroupBy("|(key, _)| key.len()".to_string())]
+            );
+            assert_eq!(tokens, vec![Variable("var".to_string(), None, vec![])]);
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_transpose() {
+        let mut source: Peekable<Chars> = "(transpose: $var)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, _, _, modifiers) in {
+            assert_eq!(modifiers, vec![GroupModifier::Transpose]);
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_selection() {
+        let mut source: Peekable<Chars> = "(checkbox(selected): $var)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, _, _, modifiers) in {
+            assert_eq!(modifiers, vec![GroupModifier::Checkbox("selected".to_string())]);
+        });
+
+        let mut source: Peekable<Chars> = "(radio(|v| *v == 2): $var)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, _, _, modifiers) in {
+            assert_eq!(modifiers, vec![GroupModifier::Radio("|v| *v == 2".to_string())]);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "radio expects a selection")]
+    fn test_parse_group_with_empty_selection() {
+        let mut source: Peekable<Chars> = "(radio: $var)*".chars().peekable();
+        parse_group(&mut source, '$').unwrap();
+    }
+
+    #[test]
+    fn test_parse_group_with_separate_by() {
+        let mut source: Peekable<Chars> = "(separate_by(|n| n / 10): $var)( )*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, _, _, modifiers) in {
+            assert_eq!(modifiers, vec![GroupModifier::SeparateBy("|n| n / 10".to_string())]);
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_skip_empty() {
+        let mut source: Peekable<Chars> = "(skip_empty: $var),*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, _, _, modifiers) in {
+            assert_eq!(modifiers, vec![GroupModifier::SkipEmpty]);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "group_by expects a key")]
+    fn test_parse_group_with_empty_group_by() {
+        let mut source: Peekable<Chars> = "(group_by(): $var)*".chars().peekable();
+        parse_group(&mut source, '$').unwrap();
+    }
+
+    #[test]
+    fn test_parse_binding_with_by_value_group() {
+        let mut source: Peekable<Chars> = "~(sort_by_value: $var)*".chars().peekable();
+        let token = parse_binding(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(_, _, _, modifiers) in {
+            assert_eq!(
+                modifiers,
+                vec![
+                    GroupModifier::IntoIter,
+                    GroupModifier::SortByValue(Order::Ascending)
+                ]
+            );
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "expected (")]
+    fn test_parse_binding_with_by_value_variable() {
+        let mut source: Peekable<Chars> = "~var".chars().peekable();
+        parse_binding(&mut source, '$').unwrap();
+    }
+
+    #[test]
+    fn test_parse_group_with_unknown_modifier() {
+        let mut source: Peekable<Chars> = "(key: $var)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, _, _, modifiers) in {
+            assert_eq!(modifiers, vec![]);
+            expect_match!(&tokens[0] => QuoteToken::Literal(literal) in assert_eq!(literal, "key: "));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "expected asc or desc")]
+    fn test_parse_group_with_invalid_modifier_argument() {
+        let mut source: Peekable<Chars> = "(sort_by_value(up): $var)*".chars().peekable();
+        parse_group(&mut source, '$').unwrap();
+    }
+
+    #[test]
+    fn test_parse_conditional() {
+        let mut source: Peekable<Chars> = "(if verbose: debug $info)".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        assert_eq!(
+            token,
+            Conditional(
+                "verbose".to_string(),
+                false,
+                None,
+                vec![
+                    Literal("debug ".to_string()),
+                    Variable("info".to_string(), None, vec![])
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_conditional_with_pattern() {
+        let mut source: Peekable<Chars> = "(if {user:(name, _)}: $name)rest".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => Conditional(ident, _, pattern, tokens) in {
+            assert_eq!(ident, "user");
+            assert_eq!(
+                pattern,
+                Some(Pattern::Tuple(vec![
+                    Pattern::Ident("name".to_string()),
+                    Pattern::Ident("_".to_string())
+                ]))
+            );
+            assert_eq!(tokens, vec![Variable("name".to_string(), None, vec![])]);
+        });
+        assert_eq!(source.collect::<String>(), "rest");
+    }
+
+    #[test]
+    fn test_parse_nested_conditional() {
+        let tokens = parse("$(if a: $(if b: both))", '$').unwrap();
+
+        assert_eq!(
+            tokens,
+            vec![Conditional(
+                "a".to_string(),
+                false,
+                None,
+                vec![Conditional(
+                    "b".to_string(),
+                    false,
+                    None,
+                    vec![Literal("both".to_string())]
+                )]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_negated_conditional() {
+        let mut source: Peekable<Chars> = "(if !#first: , )".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        assert_eq!(
+            token,
+            Conditional(
+                "#first".to_string(),
+                true,
+                None,
+                vec![Literal(", ".to_string())]
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "negated conditions can not bind a pattern")]
+    fn test_parse_negated_conditional_with_pattern() {
+        let mut source: Peekable<Chars> = "(if !{user:name}: $name)".chars().peekable();
+        parse_group(&mut source, '$').unwrap();
+    }
+
+    #[test]
+    fn test_parse_group_starting_with_if() {
+        let mut source: Peekable<Chars> = "(if $var)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => Group(tokens, _, _, _) in {
+            expect_match!(&tokens[0] => Literal(literal) in assert_eq!(literal, "if "));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "conditions can not have modifiers")]
+    fn test_parse_conditional_with_modifier() {
+        let mut source: Peekable<Chars> = "(if {flag|zero_pad}: yes)".chars().peekable();
+        parse_group(&mut source, '$').unwrap();
+    }
+
+    #[test]
+    fn test_parse_group_with_variable() {
+        let mut source: Peekable<Chars> = "(literal $var)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, _, _, _) in {
+            expect_match!(&tokens[0] => QuoteToken::Literal(literal) in assert_eq!(literal, "literal "));
+            expect_match!(
+                &tokens[1] => QuoteToken::Variable(ident, inner_ident, _) in {
+                    assert_eq!(ident, "var");
+                    assert_eq!(inner_ident, &None);
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_variable_and_trailing_literal() {
+        let mut source: Peekable<Chars> = "(literal1 $variable literal2)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, _, _, _) in {
+            expect_match!(&tokens[0] => QuoteToken::Literal(literal) in assert_eq!(literal, "literal1 "));
+            expect_match!(
+                &tokens[1] => QuoteToken::Variable(ident, inner_ident, _) in {
+                    assert_eq!(ident, "variable");
+                    assert_eq!(inner_ident, &None);
+                }
+            );
+            expect_match!(&tokens[2] => QuoteToken::Literal(literal) in assert_eq!(literal, " literal2"));
+        });
+    }
+    #[test]
+    fn test_parse_group_with_hidden_variable() {
+        let mut source: Peekable<Chars> = "(literal @var)*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, _, _, _) in {
+            expect_match!(&tokens[0] => QuoteToken::Literal(literal) in assert_eq!(literal, "literal "));
+            expect_match!(
+                &tokens[1] => QuoteToken::HiddenVariable(ident, inner_ident) in {
+                    assert_eq!(ident, "var");
+                    assert_eq!(inner_ident, &None);
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_hidden_variable_and_trailing_literal() {
+        let mut source: Peekable<Chars> = "(literal1 @variable literal2)**".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, _, _, _) in {
+            expect_match!(&tokens[0] => QuoteToken::Literal(literal) in assert_eq!(literal, "literal1 "));
+            expect_match!(
+                &tokens[1] => QuoteToken::HiddenVariable(ident, inner_ident) in {
+                    assert_eq!(ident, "variable");
+                    assert_eq!(inner_ident, &None);
+                }
+            );
+            expect_match!(&tokens[2] => QuoteToken::Literal(literal) in assert_eq!(literal, " literal2"));
+        });
+    }
+    #[test]
+    #[should_panic]
+    fn test_parse_group_unexpected_end() {
+        let mut source: Peekable<Chars> = "(".chars().peekable();
+        parse_group(&mut source, '$').unwrap();
+    }
+
+    #[test]
+    fn test_parse_group_with_balanced_parenthesis() {
+        let expected_literal = "literal () ((literal), ((), ()))";
+
+        let mut source: Peekable<Chars> = "(literal () ((literal), ((), ())))*".chars().peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, _, _, _) in {
+            expect_match!(&tokens[0] => QuoteToken::Literal(literal) in assert_eq!(literal, expected_literal));
+        });
+    }
+
+    #[test]
+    fn test_parse_group_with_unbalanced_parenthesis() {
+        let expected_literal = "literal ( () ((literal, ((, ()))))";
+
+        let mut source: Peekable<Chars> = ("(literal \\( () (\\(literal, (\\(, ()))\\)\\))*")
+            .chars()
+            .peekable();
+        let token = parse_group(&mut source, '$').unwrap();
+
+        expect_match!(token => QuoteToken::Group(tokens, _, _, _) in {
+            expect_match!(&tokens[0] => QuoteToken::Literal(literal) in assert_eq!(literal, expected_literal));
+        });
+    }
+    #[test]
+    fn test_parse_binding_with_variable() {
+        let mut source: Peekable<Chars> = "variable".chars().peekable();
+        let token = parse_binding(&mut source, '$').unwrap();
+
+        expect_match!(
+            token => QuoteToken::Variable(ident, inner_ident, _) in {
+                assert_eq!(ident, "variable");
+                assert_eq!(inner_ident, None);
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_binding_invalid_start() {
+        let mut source: Peekable<Chars> = "1invalid".chars().peekable();
+        parse_binding(&mut source, '$').unwrap();
+    }
+
+    #[test]
+    fn test_parse_variable_idents_with_braces() {
+        let mut source: Peekable<Chars> = "{foo:bar}".chars().peekable();
+        let (ident, inner_ident, _) = parse_variable_idents(&mut source).unwrap();
+
+        assert_eq!(ident, "foo");
+        assert_eq!(inner_ident, Some(Pattern::Ident("bar".to_string())));
+    }
+
+    #[test]
+    fn test_parse_variable_idents_with_braces_single_ident() {
+        let mut source: Peekable<Chars> = "{foo}".chars().peekable();
+        let (ident, inner_ident, _) = parse_variable_idents(&mut source).unwrap();
+
+        assert_eq!(ident, "foo");
+        assert_eq!(inner_ident, None);
+    }
+
+    #[test]
+    fn test_parse_variable_idents_without_braces() {
+        let mut source: Peekable<Chars> = "foo".chars().peekable();
+        let (ident, inner_ident, _) = parse_variable_idents(&mut source).unwrap();
+
+        assert_eq!(ident, "foo");
+        assert_eq!(inner_ident, None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_variable_idents_invalid_start_with_braces() {
+        let mut source: Peekable<Chars> = "{1foo:bar}".chars().peekable();
+        parse_variable_idents(&mut source).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_variable_idents_invalid_start_without_braces() {
+        let mut source: Peekable<Chars> = "1foo".chars().peekable();
+        parse_variable_idents(&mut source).unwrap();
+    }
+
+    #[test]
+    fn test_parse_ident_valid() {
+        let mut source: Peekable<Chars> = "foo123_".chars().peekable();
+        let ident = parse_ident(&mut source).unwrap();
+
+        assert_eq!(ident, "foo123_");
+    }
+
+    #[test]
+    fn test_parse_ident_start_with_underscore() {
+        let mut source: Peekable<Chars> = "_foo".chars().peekable();
+        let ident = parse_ident(&mut source).unwrap();
+
+        assert_eq!(ident, "_foo");
+    }
+
+    #[test]
+    #[should_panic(expected = "expected identifier")]
+    fn test_parse_ident_start_with_number() {
+        let mut source: Peekable<Chars> = "1foo".chars().peekable();
+        parse_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "expected identifier")]
+    fn test_parse_ident_start_with_special_char() {
+        let mut source: Peekable<Chars> = "@foo".chars().peekable();
+        parse_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    fn test_parse_ident_stops_at_special_char() {
+        let mut source: Peekable<Chars> = "foo@".chars().peekable();
+        let ident = parse_ident(&mut source).unwrap();
+
+        assert_eq!(ident, "foo");
+    }
+
+    #[test]
+    fn test_parse_bound_ident_only_ident() {
+        let mut source: Peekable<Chars> = "{foo}".chars().peekable();
+        let (ident, inner_ident, _) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(ident, "foo");
+        assert_eq!(inner_ident, None);
+    }
+
+    #[test]
+    fn test_parse_bound_ident_with_inner_ident() {
+        let mut source: Peekable<Chars> = "{foo:bar}".chars().peekable();
+        let (ident, inner_ident, _) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(ident, "foo");
+        assert_eq!(inner_ident, Some(Pattern::Ident("bar".to_string())));
+    }
+
+    #[test]
+    fn test_parse_bound_ident_with_tuple_pattern() {
+        let mut source: Peekable<Chars> = "{map:(key, (a,b))}".chars().peekable();
+        let (ident, inner_pattern, _) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(ident, "map");
+        assert_eq!(
+            inner_pattern,
+            Some(Pattern::Tuple(vec![
+                Pattern::Ident("key".to_string()),
+                Pattern::Tuple(vec![
+                    Pattern::Ident("a".to_string()),
+                    Pattern::Ident("b".to_string())
+                ])
+            ]))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "expected , or )")]
+    fn test_parse_bound_ident_with_unclosed_tuple_pattern() {
+        let mut source: Peekable<Chars> = "{map:(key, value}".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    fn test_parse_bound_ident_with_modifier() {
+        let mut source: Peekable<Chars> = "{foo:bar|zero_pad}".chars().peekable();
+        let (ident, inner_ident, modifiers) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(ident, "foo");
+        assert_eq!(inner_ident, Some(Pattern::Ident("bar".to_string())));
+        assert_eq!(modifiers, vec![Modifier::ZeroPad]);
+    }
+
+    #[test]
+    fn test_parse_bound_ident_with_modifier_arguments() {
+        let mut source: Peekable<Chars> = "{foo|cell=8|cell(4)}".chars().peekable();
+        let (_, _, modifiers) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(modifiers, vec![Modifier::Cell(8), Modifier::Cell(4)]);
+    }
+
+    #[test]
+    fn test_parse_bound_ident_with_optional_modifier_argument() {
+        let mut source: Peekable<Chars> = "{foo|hexdump|hexdump=8}".chars().peekable();
+        let (_, _, modifiers) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(modifiers, vec![Modifier::Hexdump(16), Modifier::Hexdump(8)]);
+    }
+
+    #[test]
+    fn test_parse_bar_modifier() {
+        let mut source: Peekable<Chars> = "{foo|bar=20|bar(10, =.)}".chars().peekable();
+        let (_, _, modifiers) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(
+            modifiers,
+            vec![Modifier::Bar(20, '#', '-'), Modifier::Bar(10, '=', '.')]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "bar expects two characters for the filled and the empty part")]
+    fn test_parse_bar_modifier_with_invalid_characters() {
+        let mut source: Peekable<Chars> = "{foo|bar(10, =)}".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    fn test_parse_dsv_modifier() {
+        let mut source: Peekable<Chars> = "{foo|csv|dsv(\"\t\")|dsv(;)}".chars().peekable();
+        let (_, _, modifiers) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(
+            modifiers,
+            vec![Modifier::Dsv(','), Modifier::Dsv('\t'), Modifier::Dsv(';')]
+        );
+    }
+
+    #[test]
+    fn test_parse_money_modifier() {
+        let mut source: Peekable<Chars> = "{foo|money(\"$\")|money(EUR )|money}".chars().peekable();
+        let (_, _, modifiers) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(
+            modifiers,
+            vec![
+                Modifier::Money("$".to_string()),
+                Modifier::Money("EUR".to_string()),
+                Modifier::Money(String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_diff_modifier() {
+        let mut source: Peekable<Chars> = "{foo|diff(+)|diff(\" \")}".chars().peekable();
+        let (_, _, modifiers) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(
+            modifiers,
+            vec![
+                Modifier::Diff("+".to_string()),
+                Modifier::Diff(" ".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "diff expects a marker")]
+    fn test_parse_diff_modifier_without_marker() {
+        let mut source: Peekable<Chars> = "{foo|diff}".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "dsv expects a delimiter character")]
+    fn test_parse_dsv_modifier_with_quote_delimiter() {
+        let mut source: Peekable<Chars> = "{foo|dsv(\"\"\")}".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    fn test_parse_wrap_modifier() {
+        let mut source: Peekable<Chars> = "{foo|wrap=72|wrap=72, hang = 4}".chars().peekable();
+        let (_, _, modifiers) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(
+            modifiers,
+            vec![Modifier::Wrap(72, 0), Modifier::Wrap(72, 4)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "hang must be less than the width of wrap")]
+    fn test_parse_wrap_modifier_with_too_large_hang() {
+        let mut source: Peekable<Chars> = "{foo|wrap=4,hang=4}".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "justify expects a number")]
+    fn test_parse_justify_modifier_without_width() {
+        let mut source: Peekable<Chars> = "{foo|justify}".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "box does not take an argument")]
+    fn test_parse_box_modifier_with_argument() {
+        let mut source: Peekable<Chars> = "{foo|box=2}".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    fn test_parse_format_spec() {
+        let parse_spec = |spec: &str| parse_format_spec(&mut spec.chars().peekable()).unwrap();
+
+        assert_eq!(
+            parse_spec(">8"),
+            FormatSpec {
+                align: Some(Align::Right),
+                width: Some(8),
+                ..FormatSpec::default()
+            }
+        );
+        assert_eq!(
+            parse_spec("0>3"),
+            FormatSpec {
+                fill: Some('0'),
+                align: Some(Align::Right),
+                width: Some(3),
+                ..FormatSpec::default()
+            }
+        );
+        assert_eq!(
+            parse_spec("*^10"),
+            FormatSpec {
+                fill: Some('*'),
+                align: Some(Align::Center),
+                width: Some(10),
+                ..FormatSpec::default()
+            }
+        );
+        assert_eq!(
+            parse_spec("05"),
+            FormatSpec {
+                zero: true,
+                width: Some(5),
+                ..FormatSpec::default()
+            }
+        );
+        assert_eq!(
+            parse_spec("<"),
+            FormatSpec {
+                align: Some(Align::Left),
+                ..FormatSpec::default()
+            }
+        );
+        assert_eq!(
+            parse_spec(".2"),
+            FormatSpec {
+                precision: Some(2),
+                ..FormatSpec::default()
+            }
+        );
+        assert_eq!(
+            parse_spec(".>8.3"),
+            FormatSpec {
+                fill: Some('.'),
+                align: Some(Align::Right),
+                width: Some(8),
+                precision: Some(3),
+                ..FormatSpec::default()
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid format spec 8.")]
+    fn test_parse_format_spec_without_precision() {
+        parse_format_spec(&mut "8.".chars().peekable()).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid format spec >x")]
+    fn test_parse_invalid_format_spec() {
+        parse_format_spec(&mut ">x".chars().peekable()).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid fill character {")]
+    fn test_parse_format_spec_with_invalid_fill() {
+        parse_format_spec(&mut "{<3".chars().peekable()).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "cell expects a number")]
+    fn test_parse_bound_ident_with_invalid_number_argument() {
+        let mut source: Peekable<Chars> = "{foo|cell=wide}".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown modifier unknown")]
+    fn test_parse_bound_ident_with_unknown_modifier() {
+        let mut source: Peekable<Chars> = "{foo|unknown=1}".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    fn test_parse_bound_ident_with_bare_filter() {
+        let mut source: Peekable<Chars> = "{foo|shout|zero_pad}".chars().peekable();
+        let (_, _, modifiers) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(modifiers[0], Modifier::Filter("shout".to_string()));
+        assert_eq!(modifiers[1], Modifier::ZeroPad);
+    }
+
+    #[test]
+    fn test_parse_bound_ident_with_filter() {
+        let mut source: Peekable<Chars> = "{foo|filter(shout)|filter(text::snake)|>8}"
+            .chars()
+            .peekable();
+        let (_, _, modifiers) = parse_bound_ident(&mut source).unwrap();
+
+        assert_eq!(modifiers[0], Modifier::Filter("shout".to_string()));
+        assert_eq!(modifiers[1], Modifier::Filter("text::snake".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "filter expects the path of a filter, found text::")]
+    fn test_parse_bound_ident_with_invalid_filter_path() {
+        let mut source: Peekable<Chars> = "{foo|filter(text::)}".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "zero_pad does not take an argument")]
+    fn test_parse_bound_ident_with_unexpected_modifier_argument() {
+        let mut source: Peekable<Chars> = "{foo|zero_pad=3}".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "only hidden variables can be destructured")]
+    fn test_parse_variable_with_tuple_pattern() {
+        let mut source: Peekable<Chars> = "{pair:(a, b)}".chars().peekable();
+        parse_variable(&mut source).unwrap();
+    }
+
+    #[test]
+    fn test_parse_reserved_variable() {
+        let mut source: Peekable<Chars> = "{#index0|zero_pad}".chars().peekable();
+        let token = parse_variable(&mut source).unwrap();
+
+        assert_eq!(
+            token,
+            Variable("#index0".to_string(), None, vec![Modifier::ZeroPad])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown reserved name #idx")]
+    fn test_parse_unknown_reserved_variable() {
+        let mut source: Peekable<Chars> = "#idx".chars().peekable();
+        parse_variable(&mut source).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "#count can not be bound to a pattern")]
+    fn test_parse_reserved_variable_with_pattern() {
+        let mut source: Peekable<Chars> = "{#count:n}".chars().peekable();
+        parse_variable(&mut source).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "hidden variables can not have modifiers")]
+    fn test_parse_hidden_variable_with_modifier() {
+        let mut source: Peekable<Chars> = "{foo|zero_pad}".chars().peekable();
+        parse_hidden_variable(&mut source).unwrap();
+    }
+
+    #[test]
+    fn test_parse_hidden_variable_with_count() {
+        let mut source: Peekable<Chars> = "3 line".chars().peekable();
+        assert_eq!(
+            parse_hidden_variable(&mut source).unwrap(),
+            QuoteToken::HiddenVariable("3".to_string(), None)
+        );
+
+        let mut source: Peekable<Chars> = "{12:i}".chars().peekable();
+        assert_eq!(
+            parse_hidden_variable(&mut source).unwrap(),
+            QuoteToken::HiddenVariable("12".to_string(), Some(Pattern::Ident("i".to_string())))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "expected : or }")]
+    fn test_parse_hidden_variable_with_invalid_count() {
+        let mut source: Peekable<Chars> = "{3x}".chars().peekable();
+        parse_hidden_variable(&mut source).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "expected : or }")]
+    fn test_parse_bound_ident_with_invalid_char() {
+        let mut source: Peekable<Chars> = "{foo;".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "expected }")]
+    fn test_parse_bound_ident_missing_closing_brace() {
+        let mut source: Peekable<Chars> = "{foo:bar".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "expected {")]
+    fn test_parse_bound_ident_missing_opening_brace() {
+        let mut source: Peekable<Chars> = "foo".chars().peekable();
+        parse_bound_ident(&mut source).unwrap();
+    }
+}
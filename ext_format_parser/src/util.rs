@@ -0,0 +1,453 @@
+use crate::parse::ParseError;
+
+/// Splits a line into its content and its line terminator.
+///
+/// Both `\n` and `\r\n` are recognized as terminators, so a trailing `\r` is never
+/// considered part of the line content.
+///
+fn split_line_terminator(line: &str) -> (&str, &str) {
+    if let Some(content) = line.strip_suffix("\r\n") {
+        (content, "\r\n")
+    } else if let Some(content) = line.strip_suffix('\n') {
+        (content, "\n")
+    } else {
+        (line, "")
+    }
+}
+
+/// The number of columns a tab advances the indentation to, when measuring indentation.
+const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// Calculates the column a whitespace character advances the indentation to.
+///
+/// Tabs advance to the next multiple of `tab_width`, all other whitespace advances by one column.
+///
+fn advance_column(column: usize, ch: char, tab_width: usize) -> usize {
+    if ch == '\t' {
+        (column / tab_width + 1) * tab_width
+    } else {
+        column + 1
+    }
+}
+
+/// Calculates the indentation of a single line in columns.
+///
+fn get_line_indent(line: &str, tab_width: usize) -> usize {
+    line.chars()
+        .take_while(|ch| ch.is_whitespace())
+        .fold(0, |column, ch| advance_column(column, ch, tab_width))
+}
+
+/// Strips the marker of a line whose indentation is kept by [`unindent`].
+///
+/// Lines are marked by a `\` at their very start, directly followed by a space or tab.
+///
+fn strip_keep_indent_marker(line: &str) -> Option<&str> {
+    line.strip_prefix('\\')
+        .filter(|rest| rest.starts_with([' ', '\t']))
+}
+
+/// Calculates the minimum indentation level of a multiline string in columns.
+///
+/// This function scans each line in the input string to find the line with the least
+/// amount of leading whitespace, ignoring lines that only contain whitespace and lines
+/// marked to keep their indentation.
+///
+fn get_indent_level(source: &str, tab_width: usize) -> usize {
+    let mut min_indent = usize::MAX;
+    for line in source.split_inclusive('\n') {
+        let (line, _) = split_line_terminator(line);
+        if line.trim() == "" || strip_keep_indent_marker(line).is_some() {
+            continue;
+        }
+        let indent = get_line_indent(line, tab_width);
+        if indent < min_indent {
+            min_indent = indent;
+        }
+        if min_indent == 0 {
+            break;
+        }
+    }
+    min_indent
+}
+
+/// Removes `indent` columns of leading whitespace from a line.
+///
+/// If a tab spans past the removed columns, the remaining columns are kept as spaces.
+///
+fn strip_indent(line: &str, indent: usize, tab_width: usize) -> String {
+    let mut column = 0;
+    for (index, ch) in line.char_indices() {
+        if column >= indent || !ch.is_whitespace() {
+            return " ".repeat(column.saturating_sub(indent)) + &line[index..];
+        }
+        column = advance_column(column, ch, tab_width);
+    }
+    " ".repeat(column.saturating_sub(indent))
+}
+
+/// Unindents a multi-line string by removing a uniform level of indentation from each line.
+///
+/// Indentation is measured in columns, with tabs advancing to the next multiple of 4 columns.
+/// Line terminators (`\n` or `\r\n`) are preserved as they appear in the source.
+/// Lines starting with `\` followed by whitespace keep their indentation, only the `\` is removed.
+///
+pub fn unindent(source: &str) -> String {
+    unindent_with_tab_width(source, DEFAULT_TAB_WIDTH)
+}
+
+/// Unindents a multi-line string like [`unindent`], with tabs advancing to the next multiple
+/// of `tab_width` columns instead of 4.
+///
+/// ```rust
+/// use ext_format_parser::unindent_with_tab_width;
+///
+/// assert_eq!(unindent_with_tab_width("\tfoo\n    bar", 2), "foo\n  bar");
+/// ```
+///
+/// # Panics
+///
+/// Panics if `tab_width` is 0.
+///
+pub fn unindent_with_tab_width(source: &str, tab_width: usize) -> String {
+    assert!(tab_width > 0, "tab width must be at least 1");
+    let indent = get_indent_level(source, tab_width);
+
+    let mut res = String::new();
+    for line in source.split_inclusive('\n') {
+        let (line, terminator) = split_line_terminator(line);
+        if let Some(line) = strip_keep_indent_marker(line) {
+            res.push_str(line);
+        } else if get_line_indent(line, tab_width) > indent || line.trim() != "" {
+            res.push_str(&strip_indent(line, indent, tab_width));
+        } else {
+            res.push_str(line);
+        }
+        res.push_str(terminator);
+    }
+    res
+}
+
+/// Converts two hexadecimal characters to a single byte.
+///
+fn byte_from_hex_chars(first_hex: char, second_hex: char) -> u8 {
+    let ordinal = format!("{}{}", first_hex, second_hex);
+    u8::from_str_radix(&ordinal, 16).unwrap()
+}
+
+/// Decodes the bytes of consecutive `\xHH` escapes as UTF-8 and appends them to `res`.
+///
+/// Fails if the bytes are not valid UTF-8, e.g. an incomplete multi-byte sequence.
+///
+fn flush_bytes(res: &mut String, bytes: &mut Vec<u8>) -> Result<(), ParseError> {
+    if bytes.is_empty() {
+        return Ok(());
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(decoded) => res.push_str(decoded),
+        Err(_) => {
+            return Err(ParseError::new(format!(
+                "invalid UTF-8 in hex escapes: {}",
+                bytes
+                    .iter()
+                    .map(|byte| format!("\\x{:02x}", byte))
+                    .collect::<String>()
+            )))
+        }
+    }
+    bytes.clear();
+    Ok(())
+}
+
+/// Unescapes a string by converting escape sequences to their character representations.
+///
+/// Recognizes common escape sequences like `\\`, `\n`, `\r`, and `\t`. Also supports
+/// hexadecimal escapes in the form of `\xHH` where `H` is a hexadecimal digit. Consecutive
+/// hexadecimal escapes are decoded as UTF-8, so `\xc3\xa9` becomes `é`.
+///
+pub fn unescape(s: &str) -> Result<String, ParseError> {
+    let mut res = String::new();
+    let mut bytes = vec![];
+
+    let mut chars = s.chars();
+    while let Some(char) = chars.next() {
+        if '\\' == char {
+            if let Some(next_char) = chars.next() {
+                if next_char == 'x' {
+                    match (chars.next(), chars.next()) {
+                        (Some(first_hex), Some(second_hex))
+                            if first_hex.is_ascii_hexdigit() && second_hex.is_ascii_hexdigit() =>
+                        {
+                            bytes.push(byte_from_hex_chars(first_hex, second_hex));
+                            continue;
+                        }
+                        (Some(first_hex), Some(second_hex)) => {
+                            flush_bytes(&mut res, &mut bytes)?;
+                            res.push_str(&format!(r"\x{}{}", first_hex, second_hex));
+                        }
+                        (Some(first_hex), None) => {
+                            flush_bytes(&mut res, &mut bytes)?;
+                            res.push_str(&format!(r"\x{}", first_hex));
+                        }
+                        (_, _) => {
+                            flush_bytes(&mut res, &mut bytes)?;
+                            res.push_str(r"\x");
+                        }
+                    }
+                    continue;
+                }
+                flush_bytes(&mut res, &mut bytes)?;
+                match next_char {
+                    '\\' => res.push('\\'),
+                    'n' => res.push('\n'),
+                    'r' => res.push('\r'),
+                    't' => res.push('\t'),
+                    c => res.push_str(&format!(r"\{}", c)),
+                }
+            } else {
+                flush_bytes(&mut res, &mut bytes)?;
+                res.push(char);
+            }
+        } else {
+            flush_bytes(&mut res, &mut bytes)?;
+            res.push(char);
+        }
+    }
+    flush_bytes(&mut res, &mut bytes)?;
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unescape;
+    use super::unindent;
+    use super::unindent_with_tab_width;
+
+    #[test]
+    fn test_unindent_basic() {
+        let original = "    Line1\n    Line2";
+        let expected = "Line1\nLine2";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unindent_mixed_indent() {
+        let original = "    Line1\n  Line2";
+        let expected = "  Line1\nLine2";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unindent_no_indent() {
+        let original = "Line1\nLine2";
+        let expected = "Line1\nLine2";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unindent_empty_string() {
+        let original = "";
+        let expected = "";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unindent_single_line() {
+        let original = "    Line1";
+        let expected = "Line1";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unindent_new_lines_only() {
+        let original = "\n\n\n";
+        let expected = "\n\n\n";
+        assert_eq!(unindent(original), expected);
+    }
+    #[test]
+    fn test_unindent_empty_lines() {
+        let original = "    Line1\n  \n  Line2";
+        let expected = "  Line1\n  \nLine2";
+        assert_eq!(unindent(original), expected);
+    }
+    #[test]
+    fn test_unindent_crlf() {
+        let original = "    Line1\r\n      Line2\r\n    Line3";
+        let expected = "Line1\r\n  Line2\r\nLine3";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unindent_mixed_line_endings() {
+        let original = "\r\n    Line1\n      Line2\r\n    \r\n    Line3\n";
+        let expected = "\r\nLine1\n  Line2\r\n    \r\nLine3\n";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unindent_crlf_empty_lines() {
+        let original = "  Line1\r\n\r\n  Line2\r\n";
+        let expected = "Line1\r\n\r\nLine2\r\n";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unindent_tabs() {
+        let original = "\tLine1\n\t\tLine2";
+        let expected = "Line1\n\tLine2";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unindent_tabs_and_spaces() {
+        let original = "\tLine1\n    Line2\n      Line3";
+        let expected = "Line1\nLine2\n  Line3";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unindent_tab_wider_than_indent() {
+        let original = "  Line1\n\tLine2";
+        let expected = "Line1\n  Line2";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unindent_tab_after_spaces() {
+        let original = "  \tLine1\n    Line2";
+        let expected = "Line1\nLine2";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unindent_custom_tab_width() {
+        let original = "\tLine1\n  Line2";
+        let expected = "Line1\nLine2";
+        assert_eq!(unindent_with_tab_width(original, 2), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "tab width must be at least 1")]
+    fn test_unindent_zero_tab_width() {
+        unindent_with_tab_width("\tLine1", 0);
+    }
+
+    #[test]
+    fn test_unindent_keep_indent_marker() {
+        let original = "    Line1\n\\  Line2\n\\\tLine3\n      Line4";
+        let expected = "Line1\n  Line2\n\tLine3\n  Line4";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unindent_escape_at_line_start() {
+        let original = "\\$Line1\n    Line2";
+        let expected = "\\$Line1\n    Line2";
+        assert_eq!(unindent(original), expected);
+    }
+
+    #[test]
+    fn test_unescape_empty_string() {
+        assert_eq!(unescape("").unwrap(), "");
+    }
+
+    #[test]
+    fn test_unescape_no_escape_characters() {
+        assert_eq!(unescape("hello").unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_unescape_newline() {
+        assert_eq!(unescape("hello\\nworld").unwrap(), "hello\nworld");
+    }
+
+    #[test]
+    fn test_unescape_tab() {
+        assert_eq!(unescape("hello\\tworld").unwrap(), "hello\tworld");
+    }
+
+    #[test]
+    fn test_unescape_return() {
+        assert_eq!(unescape("hello\\rworld").unwrap(), "hello\rworld");
+    }
+
+    #[test]
+    fn test_unescape_backslash() {
+        assert_eq!(unescape("hello\\\\world").unwrap(), "hello\\world");
+    }
+
+    #[test]
+    fn test_unescape_hex() {
+        assert_eq!(unescape("hello\\x41world").unwrap(), "helloAworld");
+    }
+
+    #[test]
+    fn test_unescape_hex_lowercase() {
+        assert_eq!(unescape("hello\\x41world").unwrap(), "helloAworld");
+        assert_eq!(unescape("hello\\x41world").unwrap(), "helloAworld");
+    }
+
+    #[test]
+    fn test_unescape_hex_ascii_boundaries() {
+        assert_eq!(unescape("\\x00\\x7F").unwrap(), "\u{0}\u{7f}");
+    }
+
+    #[test]
+    fn test_unescape_hex_multi_byte() {
+        assert_eq!(unescape("caf\\xc3\\xa9!").unwrap(), "café!");
+        assert_eq!(unescape("\\xe2\\x82\\xac\\x41").unwrap(), "€A");
+        assert_eq!(unescape("\\xF0\\x9F\\xA6\\x80").unwrap(), "🦀");
+    }
+
+    #[test]
+    fn test_unescape_hex_incomplete_multi_byte() {
+        assert_eq!(
+            unescape("\\xc3 ").unwrap_err().to_string(),
+            r"invalid UTF-8 in hex escapes: \xc3"
+        );
+    }
+
+    #[test]
+    fn test_unescape_hex_invalid_byte() {
+        assert_eq!(
+            unescape("\\xff").unwrap_err().to_string(),
+            r"invalid UTF-8 in hex escapes: \xff"
+        );
+    }
+
+    #[test]
+    fn test_invalid_first_hex() {
+        assert_eq!(unescape("hello\\xg1world").unwrap(), "hello\\xg1world");
+    }
+
+    #[test]
+    fn test_invalid_second_hex() {
+        assert_eq!(unescape("hello\\x4gworld").unwrap(), "hello\\x4gworld");
+    }
+
+    #[test]
+    fn test_unescape_incomplete_escape() {
+        assert_eq!(unescape("hello\\world").unwrap(), "hello\\world");
+        assert_eq!(unescape("hello\\").unwrap(), "hello\\");
+        assert_eq!(unescape("hello\\x").unwrap(), "hello\\x");
+        assert_eq!(unescape("hello\\x4").unwrap(), "hello\\x4");
+    }
+
+    #[test]
+    fn test_unescape_mixed() {
+        assert_eq!(
+            unescape("hello\\nworld\\tfoo\\rbar\\\\\\x41\\x42").unwrap(),
+            "hello\nworld\tfoo\rbar\\AB"
+        );
+    }
+
+    #[test]
+    fn test_unescape_trailing_backslash() {
+        assert_eq!(unescape("hello\\").unwrap(), "hello\\");
+    }
+
+    #[test]
+    fn test_unescape_trailing_hex() {
+        assert_eq!(unescape("hello\\x").unwrap(), "hello\\x");
+    }
+}
@@ -1,10 +1,20 @@
-use crate::parse::QuoteToken;
+use ext_format_parser::{
+    Align, Escape, FormatSpec, GroupModifier, Modifier, OnMissing, Order, Pattern, Pragmas,
+    QuoteToken,
+};
 use proc_macro2::Ident;
 use proc_macro2::Span;
 use proc_macro2::TokenStream;
 use quote::quote;
 use std::collections::{HashMap, HashSet};
 
+/// An error in a template which parses, but can not be turned into code, e.g. an inner name
+/// bound more than once. The macros report it as compile error.
+pub(crate) type CodegenResult<T> = Result<T, String>;
+
+/// Bindings like `name = value` passed after the template, as name and value expression.
+type Bindings = Vec<(Ident, TokenStream)>;
+
 fn get_macro_definitions() -> TokenStream {
     quote!(
         #[allow(unused_macros)]
@@ -30,163 +40,2056 @@ fn get_macro_definitions() -> TokenStream {
                     fizip!(@inner [$($r => )*] $i)
                 )
             }
+
+        // Values are written to the output with `write!`
+        #[allow(unused_imports)]
+        use std::fmt::Write as _;
+
+        // Handles missing values with `#![on_missing=error]`, `try_ext_format!` and
+        // `ext_format_args!` redefine it to return an error
+        #[allow(unused_macros)]
+        macro_rules! ext_format_missing {
+            ($message:expr) => {
+                panic!($message)
+            };
+        }
+
+        // Repetitions over a count like `@3` or a range iterate the numbers it counts,
+        // any other source is iterated as is
+        #[allow(dead_code)]
+        trait ExtFormatCount {
+            type Item;
+            fn ext_format_numbers(&self) -> Vec<Self::Item>;
+        }
+        #[allow(unused_macros)]
+        macro_rules! impl_count {
+            ($($t:ty),*) => {$(
+                impl ExtFormatCount for $t {
+                    type Item = $t;
+                    fn ext_format_numbers(&self) -> Vec<$t> {
+                        (0..*self).collect()
+                    }
+                }
+            )*};
+        }
+        impl_count!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+        impl<T> ExtFormatCount for std::ops::Range<T>
+        where
+            std::ops::Range<T>: Iterator<Item = T> + Clone,
+        {
+            type Item = T;
+            fn ext_format_numbers(&self) -> Vec<T> {
+                self.clone().collect()
+            }
+        }
+        impl<T> ExtFormatCount for std::ops::RangeInclusive<T>
+        where
+            std::ops::RangeInclusive<T>: Iterator<Item = T> + Clone,
+        {
+            type Item = T;
+            fn ext_format_numbers(&self) -> Vec<T> {
+                self.clone().collect()
+            }
+        }
+        impl<T: ExtFormatCount + ?Sized> ExtFormatCount for &T {
+            type Item = T::Item;
+            fn ext_format_numbers(&self) -> Vec<T::Item> {
+                (**self).ext_format_numbers()
+            }
+        }
+
+        #[allow(dead_code)]
+        struct ExtFormatSource<T>(T);
+        // Method resolution prefers `ExtFormatCountSource`, which needs no autoref, if its bound holds
+        #[allow(dead_code)]
+        trait ExtFormatCountSource {
+            type Output;
+            fn ext_format_source(&self) -> Self::Output;
+        }
+        impl<T: ExtFormatCount + ?Sized> ExtFormatCountSource for ExtFormatSource<&T> {
+            type Output = Vec<T::Item>;
+            fn ext_format_source(&self) -> Vec<T::Item> {
+                self.0.ext_format_numbers()
+            }
+        }
+        #[allow(dead_code)]
+        trait ExtFormatIterSource {
+            type Output;
+            fn ext_format_source(&self) -> Self::Output;
+        }
+        impl<'a, T: ?Sized> ExtFormatIterSource for &ExtFormatSource<&'a T> {
+            type Output = &'a T;
+            fn ext_format_source(&self) -> &'a T {
+                self.0
+            }
+        }
     )
-    .into()
 }
 
-pub(crate) fn generate_code(tokens: Vec<QuoteToken>) -> TokenStream {
-    let mut rust_tokens: Vec<TokenStream> = vec![];
-    for token in generate_inner_code(tokens, HashMap::new()) {
-        rust_tokens.push(token.into());
-    }
-    let inner_stream: TokenStream = TokenStream::from_iter(rust_tokens).into();
-
-    let macro_tokens = get_macro_definitions();
+pub(crate) fn generate_code(
+    tokens: Vec<QuoteToken>,
+    bindings: Vec<(Ident, TokenStream)>,
+    pragmas: Pragmas,
+) -> CodegenResult<TokenStream> {
+    let (macro_tokens, bindings, inner_stream) = generate_body_code(tokens, bindings, &pragmas)?;
+    let binding_stream = generate_binding_code(bindings);
+    let final_newline_stream = generate_final_newline_code(&pragmas);
 
-    quote!({
+    Ok(quote!({
         #macro_tokens
 
+        #binding_stream
+
         let mut res = String::new();
         #inner_stream
+        #final_newline_stream
         res
-    })
-    .into()
+    }))
 }
 
+/// Generates the code of `ext_format_with_pkg_version!`, which binds the version of the crate
+/// using the macro for `#pkg_version`.
+pub(crate) fn generate_pkg_version_code(
+    tokens: Vec<QuoteToken>,
+    mut bindings: Vec<(Ident, TokenStream)>,
+    pragmas: Pragmas,
+) -> CodegenResult<TokenStream> {
+    // `option_env!` is expanded when the calling crate is compiled, so it captures its version
+    if uses_variable(&tokens, "#pkg_version") {
+        bindings.push((
+            get_pkg_version_ident(),
+            quote!(option_env!("CARGO_PKG_VERSION").unwrap_or("")),
+        ));
+    }
+    generate_code(tokens, bindings, pragmas)
+}
+
+/// Generates an expression returning the output as `Result<String, std::fmt::Error>`.
+/// The first error of a `Display` implementation is returned instead of panicking.
+pub(crate) fn generate_try_code(
+    tokens: Vec<QuoteToken>,
+    bindings: Vec<(Ident, TokenStream)>,
+    pragmas: Pragmas,
+) -> CodegenResult<TokenStream> {
+    let (macro_tokens, bindings, inner_stream) = generate_body_code(tokens, bindings, &pragmas)?;
+    let binding_stream = generate_binding_code(bindings);
+    let final_newline_stream = generate_final_newline_code(&pragmas);
+
+    Ok(quote!({
+        #macro_tokens
+
+        #[allow(unused_macros)]
+        macro_rules! ext_format_missing {
+            ($message:expr) => {
+                return Err(std::fmt::Error)
+            };
+        }
+
+        struct ExtFormatTrySink {
+            output: String,
+            result: std::fmt::Result,
+        }
+
+        // The output is a `String` like in `ext_format!`, only `write!` goes through the sink
+        impl std::ops::Deref for ExtFormatTrySink {
+            type Target = String;
+            fn deref(&self) -> &String {
+                &self.output
+            }
+        }
+
+        impl std::ops::DerefMut for ExtFormatTrySink {
+            fn deref_mut(&mut self) -> &mut String {
+                &mut self.output
+            }
+        }
+
+        // Errors are kept in `result`, so writing to the sink itself never fails
+        impl std::fmt::Write for ExtFormatTrySink {
+            fn write_str(&mut self, string: &str) -> std::fmt::Result {
+                self.output.push_str(string);
+                Ok(())
+            }
+
+            fn write_fmt(&mut self, arguments: std::fmt::Arguments<'_>) -> std::fmt::Result {
+                if self.result.is_ok() {
+                    self.result = std::fmt::write(&mut self.output, arguments);
+                }
+                Ok(())
+            }
+        }
+
+        #binding_stream
+
+        // The closure lets missing values return early
+        (|| -> Result<String, std::fmt::Error> {
+            let mut res = ExtFormatTrySink {
+                output: String::new(),
+                result: Ok(()),
+            };
+            #inner_stream
+            #final_newline_stream
+            res.result.map(|_| res.output)
+        })()
+    }))
+}
+
+/// Generates the code making `res` end with exactly one line break for `#![final_newline]`,
+/// replacing any trailing `\n` or `\r\n` line breaks.
+fn generate_final_newline_code(pragmas: &Pragmas) -> TokenStream {
+    if !pragmas.final_newline {
+        return TokenStream::new();
+    }
+    quote!(
+        while let Some(line) = res.strip_suffix('\n') {
+            let length = line.strip_suffix('\r').unwrap_or(line).len();
+            res.truncate(length);
+        }
+        res.push('\n');
+    )
+}
+
+/// Generates a value implementing `Display`, which writes directly to the formatter
+/// instead of allocating the whole output as `String` first.
+pub(crate) fn generate_display_code(
+    tokens: Vec<QuoteToken>,
+    bindings: Vec<(Ident, TokenStream)>,
+    pragmas: Pragmas,
+) -> CodegenResult<TokenStream> {
+    let (macro_tokens, bindings, inner_stream) = generate_body_code(tokens, bindings, &pragmas)?;
+    // The bindings are evaluated once and owned by the returned value, which lends them to each
+    // call of `fmt`, while other variables are borrowed from the caller
+    let (names, expressions): (Vec<_>, Vec<_>) = bindings.into_iter().unzip();
+
+    // The trailing line breaks are only known at the end, so the output is rendered up front
+    let render_stream = if pragmas.final_newline {
+        let final_newline_stream = generate_final_newline_code(&pragmas);
+        quote!(
+            let mut res = String::new();
+            #inner_stream
+            #final_newline_stream
+            f.write_str(&res)
+        )
+    } else {
+        quote!(
+            let mut res = ExtFormatSink {
+                formatter: f,
+                result: Ok(()),
+            };
+            #inner_stream
+            res.result
+        )
+    };
+
+    Ok(quote!({
+        struct ExtFormatArgs<B, F>(B, F)
+        where
+            F: Fn(&mut std::fmt::Formatter<'_>, &B) -> std::fmt::Result;
+
+        impl<B, F> std::fmt::Display for ExtFormatArgs<B, F>
+        where
+            F: Fn(&mut std::fmt::Formatter<'_>, &B) -> std::fmt::Result,
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                (self.1)(f, &self.0)
+            }
+        }
+
+        struct ExtFormatSink<'a, 'b> {
+            formatter: &'a mut std::fmt::Formatter<'b>,
+            result: std::fmt::Result,
+        }
+
+        impl ExtFormatSink<'_, '_> {
+            fn push_str(&mut self, string: &str) {
+                if self.result.is_ok() {
+                    self.result = self.formatter.write_str(string);
+                }
+            }
+        }
+
+        // Errors are kept in `result`, so writing to the sink itself never fails
+        impl std::fmt::Write for ExtFormatSink<'_, '_> {
+            fn write_str(&mut self, string: &str) -> std::fmt::Result {
+                self.push_str(string);
+                Ok(())
+            }
+        }
+
+        ExtFormatArgs(
+            (#(#expressions,)*),
+            |f: &mut std::fmt::Formatter<'_>, bindings: &_| -> std::fmt::Result {
+                #macro_tokens
+
+                // `Display` can't panic, so missing values fail formatting like a failing value
+                #[allow(unused_macros)]
+                macro_rules! ext_format_missing {
+                    ($message:expr) => {
+                        return Err(std::fmt::Error)
+                    };
+                }
+
+                let (#(#names,)*) = bindings;
+
+                #render_stream
+            },
+        )
+    }))
+}
+
+/// Generates the helper macros and the code pushing the output to `res`, and completes the
+/// bindings with the ones the template needs.
+fn generate_body_code(
+    tokens: Vec<QuoteToken>,
+    bindings: Vec<(Ident, TokenStream)>,
+    pragmas: &Pragmas,
+) -> CodegenResult<(TokenStream, Bindings, TokenStream)> {
+    // Only `ext_format_with_pkg_version!` binds the version
+    if uses_variable(&tokens, "#pkg_version")
+        && !bindings
+            .iter()
+            .any(|(ident, _)| *ident == get_pkg_version_ident())
+    {
+        return Err(
+            "#pkg_version is captured at compile time, use ext_format_with_pkg_version! instead"
+                .to_string(),
+        );
+    }
+
+    if let Some(ident) = find_duplicate_binding(&tokens, &HashSet::new()) {
+        return Err(format!("{} is bound more than once in a repetition", ident));
+    }
+    let inner_stream = generate_inner_code(tokens, HashMap::new(), 0, pragmas)?;
+
+    let macro_tokens = get_macro_definitions();
+
+    Ok((macro_tokens, bindings, inner_stream))
+}
+
+/// Binds the values of bindings like `name = value` to their names.
+fn generate_binding_code(bindings: Bindings) -> TokenStream {
+    TokenStream::from_iter(
+        bindings
+            .into_iter()
+            .map(|(name, expression)| quote!(let #name = #expression;)),
+    )
+}
+
+/// Generates the code for a list of tokens, `depth` being the number of enclosing groups.
 fn generate_inner_code(
     tokens: Vec<QuoteToken>,
     mut mapping: HashMap<String, String>,
-) -> TokenStream {
+    depth: usize,
+    pragmas: &Pragmas,
+) -> CodegenResult<TokenStream> {
+    let counted_groups = if depth == 0 {
+        get_counted_groups(&tokens)
+    } else {
+        HashMap::new()
+    };
+    // Iterators of groups counted outside of groups are collected before any output
+    let mut hoisted_tokens: Vec<TokenStream> = vec![];
     let mut rust_tokens: Vec<TokenStream> = vec![];
-    for token in tokens {
+    for (index, token) in tokens.into_iter().enumerate() {
         let new_tokens = match token {
             QuoteToken::Literal(literal) => generate_literal_code(literal),
-            QuoteToken::Variable(ident, inner_ident) => {
-                generate_variable_code(ident, inner_ident, &mut mapping)
+            QuoteToken::Variable(ident, inner_ident, modifiers) => {
+                let ident = match counted_groups.get(&index) {
+                    Some(group) => get_group_count_ident(*group).to_string(),
+                    None => ident,
+                };
+                generate_variable_code(ident, inner_ident, modifiers, &mut mapping, depth, pragmas)?
             }
             QuoteToken::HiddenVariable(ident, inner_ident) => {
-                generate_hidden_variable_code(ident, inner_ident, &mut mapping)
+                generate_hidden_variable_code(ident, inner_ident, &mapping)
             }
-            QuoteToken::Group(tokens, separator) => generate_group_code(tokens, separator),
+            QuoteToken::Group(tokens, separator, fallback, modifiers) => {
+                let (iterator_stream, loop_stream) =
+                    generate_group_code(tokens, separator, fallback, modifiers, depth, pragmas)?;
+                // The block keeps the iterator of nested groups from shadowing the one of this group
+                if counted_groups.values().any(|group| *group == index) {
+                    let iterator_ident = Ident::new(
+                        &format!("__ext_format_iterator_{}", index),
+                        Span::call_site(),
+                    );
+                    let count_ident = get_group_count_ident(index);
+                    hoisted_tokens.push(quote!(
+                        let #iterator_ident = {
+                            #iterator_stream
+                            iterator
+                        };
+                        let #count_ident = #iterator_ident.len();
+                    ));
+                    quote!({
+                        let iterator = #iterator_ident;
+                        #loop_stream
+                    })
+                } else {
+                    quote!({
+                        #iterator_stream
+                        #loop_stream
+                    })
+                }
+            }
+            QuoteToken::Conditional(ident, negated, pattern, tokens) => generate_conditional_code(
+                ident, negated, pattern, tokens, &mapping, depth, pragmas,
+            )?,
         };
         rust_tokens.push(new_tokens);
     }
-    TokenStream::from_iter(rust_tokens)
+    Ok(TokenStream::from_iter(
+        hoisted_tokens.into_iter().chain(rust_tokens),
+    ))
+}
+
+/// Resolves `#count` variables outside of groups to the next group, or to the last group if
+/// none follows. Returns the indices of the variables mapped to the indices of their groups.
+fn get_counted_groups(tokens: &[QuoteToken]) -> HashMap<usize, usize> {
+    let groups: Vec<usize> = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| matches!(token, QuoteToken::Group(..)))
+        .map(|(index, _)| index)
+        .collect();
+    tokens
+        .iter()
+        .enumerate()
+        .filter(
+            |(_, token)| matches!(token, QuoteToken::Variable(ident, _, _) if ident == "#count"),
+        )
+        .filter_map(|(index, _)| {
+            let group = groups
+                .iter()
+                .find(|group| **group > index)
+                .or(groups.last());
+            group.map(|group| (index, *group))
+        })
+        .collect()
+}
+
+/// The number of iterations of a group outside of groups, given the index of its token.
+fn get_group_count_ident(index: usize) -> Ident {
+    Ident::new(
+        &format!("__ext_format_count_of_{}", index),
+        Span::call_site(),
+    )
+}
+
+/// Generates the code rendering the body of a conditional once if its variable is `true` or
+/// `Some`. The body belongs to the enclosing scope, so it shares the mapping of the enclosing group.
+fn generate_conditional_code(
+    ident: String,
+    negated: bool,
+    pattern: Option<Pattern>,
+    tokens: Vec<QuoteToken>,
+    mapping: &HashMap<String, String>,
+    depth: usize,
+    pragmas: &Pragmas,
+) -> CodegenResult<TokenStream> {
+    let token_stream = generate_inner_code(tokens, mapping.clone(), depth, pragmas)?;
+    generate_condition_code(
+        &ident,
+        negated,
+        pattern.as_ref(),
+        mapping,
+        depth,
+        token_stream,
+    )
+}
+
+/// Generates the code running `body_stream` if the variable of a conditional is `true` or `Some`,
+/// with the value of an `Option` bound to `pattern`.
+fn generate_condition_code(
+    ident: &str,
+    negated: bool,
+    pattern: Option<&Pattern>,
+    mapping: &HashMap<String, String>,
+    depth: usize,
+    body_stream: TokenStream,
+) -> CodegenResult<TokenStream> {
+    let var_ident = get_variable_ident(ident, mapping, depth)?;
+    let pattern_stream = match pattern {
+        Some(pattern) => generate_pattern_code(pattern),
+        None => quote!(_),
+    };
+    let test_stream = if negated {
+        quote!(if condition.is_none())
+    } else {
+        quote!(if let Some(#pattern_stream) = condition)
+    };
+    // The trait is scoped to the condition, so nested conditionals don't see multiple definitions
+    Ok(quote!({
+        let condition = {
+            trait Condition {
+                type Value;
+                fn ext_format_condition(&self) -> Option<&Self::Value>;
+            }
+            impl Condition for bool {
+                type Value = bool;
+                fn ext_format_condition(&self) -> Option<&bool> {
+                    if *self {
+                        Some(self)
+                    } else {
+                        None
+                    }
+                }
+            }
+            impl<T> Condition for Option<T> {
+                type Value = T;
+                fn ext_format_condition(&self) -> Option<&T> {
+                    self.as_ref()
+                }
+            }
+            (#var_ident).ext_format_condition()
+        };
+        #test_stream {
+            #body_stream
+        }
+    }))
 }
 
 fn generate_literal_code(literal: String) -> TokenStream {
-    let new_tokens = quote!(res.push_str(#literal););
-    new_tokens.into()
+    quote!(res.push_str(#literal);)
 }
 
 fn generate_variable_code(
     ident: String,
-    inner_ident: Option<String>,
+    inner_pattern: Option<Pattern>,
+    modifiers: Vec<Modifier>,
     mapping: &mut HashMap<String, String>,
+    depth: usize,
+    pragmas: &Pragmas,
+) -> CodegenResult<TokenStream> {
+    // Only groups map their variables, so an empty mapping means we are at the top level
+    let in_group = !mapping.is_empty();
+    let var_ident = get_variable_ident(&ident, mapping, depth)?;
+    Ok(match inner_pattern {
+        Some(Pattern::Ident(inner_ident)) if inner_ident != "_" => {
+            let inner_var_ident = Ident::new(&inner_ident, Span::call_site());
+            let push_stream = generate_push_code(
+                quote!(#inner_var_ident),
+                &ident,
+                modifiers,
+                in_group,
+                pragmas,
+            );
+            quote!(
+                let #inner_var_ident = &#var_ident;
+                #push_stream
+            )
+        }
+        Some(Pattern::Tuple(_)) => unreachable!("only hidden variables can be destructured"),
+        // `_` discards the inner name, so the value is printed like an unnamed variable
+        _ => generate_push_code(quote!(#var_ident), &ident, modifiers, in_group, pragmas),
+    })
+}
+
+/// Generates the code appending `value` to the output. Values without modifiers are written
+/// directly with their `Display` implementation, avoiding an intermediate `String`.
+/// Values are escaped according to the `escape` pragma, unless they are marked as `raw`.
+fn generate_push_code(
+    value: TokenStream,
+    ident: &str,
+    modifiers: Vec<Modifier>,
+    in_group: bool,
+    pragmas: &Pragmas,
 ) -> TokenStream {
-    let new_name = mapping.get(&ident).unwrap_or(&ident);
-    let var_ident = Ident::new(new_name, Span::call_site());
-    let new_tokens = if let Some(inner_ident) = inner_ident {
-        let inner_var_ident = Ident::new(&inner_ident, Span::call_site());
-        quote!(
-            let #inner_var_ident = #var_ident;
-            res.push_str(&#inner_var_ident.to_string());
-        )
-    } else {
-        quote!(
-            res.push_str(&#var_ident.to_string());
-        )
+    let raw = modifiers.contains(&Modifier::Raw);
+    let modifiers: Vec<_> = modifiers
+        .into_iter()
+        .filter(|modifier| *modifier != Modifier::Raw)
+        .collect();
+    if pragmas.escape == Escape::Html && !raw {
+        let value_stream = generate_modifier_code(value, ident, modifiers, in_group, pragmas);
+        return quote!(
+            for ch in #value_stream.chars() {
+                match ch {
+                    '&' => res.push_str("&amp;"),
+                    '<' => res.push_str("&lt;"),
+                    '>' => res.push_str("&gt;"),
+                    '"' => res.push_str("&quot;"),
+                    '\'' => res.push_str("&#39;"),
+                    ch => write!(res, "{}", ch).unwrap(),
+                }
+            }
+        );
+    }
+    if modifiers.is_empty() && pragmas.precision.is_none() {
+        return quote!(
+            write!(res, "{}", #value).unwrap();
+        );
+    }
+    let value_stream = generate_modifier_code(value, ident, modifiers, in_group, pragmas);
+    quote!(
+        res.push_str(&#value_stream);
+    )
+}
+
+/// Generates an expression rendering `value` to a `String`, applying all modifiers in order.
+/// Each modifier receives either the value itself or the `String` rendered by the previous one.
+fn generate_modifier_code(
+    value: TokenStream,
+    ident: &str,
+    mut modifiers: Vec<Modifier>,
+    in_group: bool,
+    pragmas: &Pragmas,
+) -> TokenStream {
+    if modifiers.is_empty() {
+        return match pragmas.precision {
+            Some(precision) => {
+                let float_format = format!("{{:.{}}}", precision);
+                generate_float_format_code(value, float_format, "{}".to_string())
+            }
+            None => quote!(#value.to_string()),
+        };
+    }
+    if modifiers[0] == Modifier::Optional {
+        modifiers.remove(0);
+        return generate_optional_code(value, ident, modifiers, in_group, pragmas);
+    }
+    // Amounts line up at the decimal point, so a width following `money` aligns right by default
+    for index in 1..modifiers.len() {
+        if matches!(modifiers[index - 1], Modifier::Money(_)) {
+            if let Modifier::Format(spec @ FormatSpec { align: None, .. }) = &mut modifiers[index] {
+                spec.align = Some(Align::Right);
+            }
+        }
+    }
+    let mut value_stream = value;
+    for modifier in modifiers {
+        value_stream = match modifier {
+            Modifier::Optional => unreachable!("${{name?}} is always the first modifier"),
+            Modifier::Raw => unreachable!("raw is removed before the value is rendered"),
+            Modifier::ZeroPad if in_group => {
+                let width_ident = get_column_width_ident(ident);
+                quote!(format!("{:0width$}", #value_stream, width = #width_ident))
+            }
+            Modifier::ZeroPad => quote!(#value_stream.to_string()),
+            Modifier::Cell(max_width) => {
+                let width_stream = if in_group {
+                    let width_ident = get_column_width_ident(ident);
+                    quote!(#width_ident.min(#max_width))
+                } else {
+                    quote!(#max_width)
+                };
+                // The value is truncated as text, as the precision of numbers means decimals.
+                // Taking chars keeps multi-byte characters intact.
+                quote!({
+                    let width = #width_stream;
+                    let content: String = #value_stream.to_string().chars().take(width).collect();
+                    format!("{:<width$}", content, width = width)
+                })
+            }
+            Modifier::Regex => quote!({
+                let mut escaped = String::new();
+                for ch in #value_stream.to_string().chars() {
+                    if "\\.+*?()|[]{}^$".contains(ch) {
+                        escaped.push('\\');
+                    }
+                    escaped.push(ch);
+                }
+                escaped
+            }),
+            // Fields are quoted like in RFC 4180, with the delimiter in place of the comma
+            Modifier::Dsv(delimiter) => quote!({
+                let field = #value_stream.to_string();
+                if field.contains([#delimiter, '"', '\n', '\r']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    field
+                }
+            }),
+            // Integers are formatted as they are, as converting them to `f64` would round
+            // amounts above 2^53
+            Modifier::Money(currency) => quote!({
+                trait Amount {
+                    // Whether the amount is negative, and its absolute value with two decimals
+                    fn amount(&self) -> (bool, String);
+                }
+                macro_rules! impl_unsigned_amount {
+                    ($($ty:ty),*) => {
+                        $(impl Amount for $ty {
+                            fn amount(&self) -> (bool, String) {
+                                (false, format!("{}.00", self))
+                            }
+                        })*
+                    };
+                }
+                macro_rules! impl_signed_amount {
+                    ($($ty:ty),*) => {
+                        $(impl Amount for $ty {
+                            fn amount(&self) -> (bool, String) {
+                                (*self < 0, format!("{}.00", self.unsigned_abs()))
+                            }
+                        })*
+                    };
+                }
+                macro_rules! impl_float_amount {
+                    ($($ty:ty),*) => {
+                        $(impl Amount for $ty {
+                            fn amount(&self) -> (bool, String) {
+                                (*self < 0.0, format!("{:.2}", self.abs()))
+                            }
+                        })*
+                    };
+                }
+                impl_unsigned_amount!(u8, u16, u32, u64, u128, usize);
+                impl_signed_amount!(i8, i16, i32, i64, i128, isize);
+                impl_float_amount!(f32, f64);
+                let (negative, digits) = (#value_stream).amount();
+                let (integer, fraction) = digits.split_at(digits.len() - 3);
+                let mut grouped = String::new();
+                for (index, digit) in integer.chars().enumerate() {
+                    if index > 0 && (integer.len() - index) % 3 == 0 {
+                        grouped.push(',');
+                    }
+                    grouped.push(digit);
+                }
+                // Amounts rounding to zero are printed without a sign
+                let negative = negative && digits.chars().any(|ch| ch.is_ascii_digit() && ch != '0');
+                format!("{}{}{}{}", if negative { "-" } else { "" }, #currency, grouped, fraction)
+            }),
+            // Splitting after each line break keeps a trailing one without prefixing an empty line
+            Modifier::Diff(marker) => quote!({
+                let mut prefixed = String::new();
+                for line in #value_stream.to_string().split_inclusive('\n') {
+                    prefixed.push_str(#marker);
+                    prefixed.push_str(line);
+                }
+                prefixed
+            }),
+            // Words longer than the width are put on a line of their own instead of being split
+            Modifier::Wrap(width, hang) => quote!({
+                let content = #value_stream.to_string();
+                let indent = " ".repeat(#hang);
+                let mut wrapped = Vec::new();
+                for paragraph in content.lines() {
+                    let mut line = String::new();
+                    let mut line_width = 0;
+                    let mut has_words = false;
+                    for word in paragraph.split_whitespace() {
+                        let word_width = word.chars().count();
+                        if has_words && line_width + 1 + word_width > #width {
+                            wrapped.push(std::mem::replace(&mut line, indent.clone()));
+                            line_width = #hang;
+                            has_words = false;
+                        }
+                        if has_words {
+                            line.push(' ');
+                            line_width += 1;
+                        }
+                        line.push_str(word);
+                        line_width += word_width;
+                        has_words = true;
+                    }
+                    wrapped.push(line);
+                }
+                wrapped.join("\n")
+            }),
+            Modifier::Bit => quote!({
+                trait Bit {
+                    fn bit(&self) -> u8;
+                }
+                impl Bit for bool {
+                    fn bit(&self) -> u8 {
+                        u8::from(*self)
+                    }
+                }
+                (#value_stream).bit().to_string()
+            }),
+            Modifier::DurationAuto => quote!({
+                trait Nanos {
+                    fn nanos(&self) -> f64;
+                }
+                macro_rules! impl_nanos {
+                    ($($ty:ty),*) => {
+                        $(impl Nanos for $ty {
+                            fn nanos(&self) -> f64 {
+                                *self as f64
+                            }
+                        })*
+                    };
+                }
+                impl_nanos!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+                impl Nanos for std::time::Duration {
+                    fn nanos(&self) -> f64 {
+                        self.as_nanos() as f64
+                    }
+                }
+                let nanos = (#value_stream).nanos();
+                // The thresholds account for rounding, so 999.9µs becomes 1.00ms instead of 1000µs
+                let (value, unit) = match nanos.abs() {
+                    magnitude if magnitude < 999.5 => (nanos, "ns"),
+                    magnitude if magnitude < 999.5e3 => (nanos / 1e3, "µs"),
+                    magnitude if magnitude < 999.5e6 => (nanos / 1e6, "ms"),
+                    _ => (nanos / 1e9, "s"),
+                };
+                // Three significant digits, except for whole nanoseconds and large second counts
+                let precision = match value.abs() {
+                    _ if unit == "ns" => 0,
+                    magnitude if magnitude < 10.0 => 2,
+                    magnitude if magnitude < 100.0 => 1,
+                    _ => 0,
+                };
+                format!("{:.precision$}{}", value, unit, precision = precision)
+            }),
+            Modifier::Filter(path) => {
+                let mut segments: Vec<&str> = path.split("::").collect();
+                let filter_ident = get_filter_ident(segments.pop().unwrap());
+                let module_idents = segments
+                    .into_iter()
+                    .map(|segment| Ident::new(segment, Span::call_site()));
+                quote!(#(#module_idents::)* #filter_ident(&#value_stream.to_string()))
+            }
+            Modifier::Format(spec) if in_group && spec.align.is_some() && spec.width.is_none() => {
+                let width_ident = get_column_width_ident(ident);
+                // The value is padded as text, so it takes up the width it was measured with
+                let content_stream = generate_modifier_code(
+                    value_stream,
+                    ident,
+                    vec![get_unpadded_modifier(&spec)],
+                    false,
+                    pragmas,
+                );
+                if spec.zero {
+                    // Like the `0` flag of numbers, the zeros go between the sign and the digits
+                    quote!({
+                        let content = #content_stream;
+                        let sign_len = if content.starts_with(['-', '+']) { 1 } else { 0 };
+                        let (sign, digits) = content.split_at(sign_len);
+                        format!(
+                            "{}{:0>width$}",
+                            sign,
+                            digits,
+                            width = #width_ident.saturating_sub(sign_len)
+                        )
+                    })
+                } else {
+                    let format_string = get_column_format_string(&spec);
+                    quote!(format!(#format_string, #content_stream, width = #width_ident))
+                }
+            }
+            Modifier::Format(spec) => match pragmas.precision {
+                Some(precision) if spec.precision.is_none() => {
+                    let float_spec = FormatSpec {
+                        precision: Some(precision),
+                        ..spec.clone()
+                    };
+                    generate_float_format_code(
+                        value_stream,
+                        get_format_string(&float_spec),
+                        get_format_string(&spec),
+                    )
+                }
+                _ => {
+                    let format_string = get_format_string(&spec);
+                    quote!(format!(#format_string, #value_stream))
+                }
+            },
+            Modifier::Hexdump(bytes_per_line) => quote!({
+                let bytes: &[u8] = AsRef::<[u8]>::as_ref(&#value_stream);
+                let mut dump = String::new();
+                for (line, chunk) in bytes.chunks(#bytes_per_line).enumerate() {
+                    if line > 0 {
+                        dump.push('\n');
+                    }
+                    let hex = chunk
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let ascii = chunk
+                        .iter()
+                        .map(|&byte| {
+                            if byte.is_ascii_graphic() || byte == b' ' {
+                                byte as char
+                            } else {
+                                '.'
+                            }
+                        })
+                        .collect::<String>();
+                    dump.push_str(&format!(
+                        "{:08x}  {:<hex_width$}  |{}|",
+                        line * #bytes_per_line,
+                        hex,
+                        ascii,
+                        hex_width = #bytes_per_line * 3 - 1
+                    ));
+                }
+                dump
+            }),
+            Modifier::Box => quote!({
+                let content = #value_stream.to_string();
+                let lines = if content.is_empty() {
+                    vec![""]
+                } else {
+                    content.lines().collect::<Vec<_>>()
+                };
+                let width = lines
+                    .iter()
+                    .map(|line| line.chars().count())
+                    .max()
+                    .unwrap_or(0);
+                let border = "─".repeat(width + 2);
+                let mut framed = format!("┌{}┐\n", border);
+                for line in lines {
+                    framed.push_str(&format!("│ {:<width$} │\n", line, width = width));
+                }
+                framed.push_str(&format!("└{}┘", border));
+                framed
+            }),
+            Modifier::Justify(width) => quote!({
+                let content = #value_stream.to_string();
+                let mut justified = Vec::new();
+                for paragraph in content.lines() {
+                    let mut lines: Vec<Vec<&str>> = vec![vec![]];
+                    let mut line_width = 0;
+                    for word in paragraph.split_whitespace() {
+                        let word_width = word.chars().count();
+                        let line = lines.last_mut().unwrap();
+                        if !line.is_empty() && line_width + 1 + word_width > #width {
+                            lines.push(vec![word]);
+                            line_width = word_width;
+                        } else {
+                            line_width += if line.is_empty() { 0 } else { 1 } + word_width;
+                            line.push(word);
+                        }
+                    }
+                    let last = lines.pop().unwrap();
+                    for words in lines {
+                        let gaps = words.len() - 1;
+                        if gaps == 0 {
+                            justified.push(words[0].to_string());
+                            continue;
+                        }
+                        let text_width: usize = words.iter().map(|word| word.chars().count()).sum();
+                        let spaces = #width.saturating_sub(text_width);
+                        let mut line = String::new();
+                        for (i, word) in words.iter().enumerate() {
+                            line.push_str(word);
+                            if i < gaps {
+                                let extra = if i < spaces % gaps { 1 } else { 0 };
+                                line.push_str(&" ".repeat(spaces / gaps + extra));
+                            }
+                        }
+                        justified.push(line);
+                    }
+                    justified.push(last.join(" "));
+                }
+                justified.join("\n")
+            }),
+            Modifier::Bar(width, filled, empty) => quote!({
+                trait Ratio {
+                    fn ratio(&self) -> f64;
+                }
+                impl Ratio for f64 {
+                    fn ratio(&self) -> f64 {
+                        *self
+                    }
+                }
+                impl Ratio for f32 {
+                    fn ratio(&self) -> f64 {
+                        *self as f64
+                    }
+                }
+                let ratio = (#value_stream).ratio().clamp(0.0, 1.0);
+                let filled = (ratio * #width as f64).round() as usize;
+                format!(
+                    "[{}{}]",
+                    #filled.to_string().repeat(filled),
+                    #empty.to_string().repeat(#width - filled)
+                )
+            }),
+        };
+    }
+    value_stream
+}
+
+/// Whether a variable is one of the reserved names, which refer to the state of an enclosing
+/// group like `#index` or `#count`, or to `#pkg_version`. The parser only accepts known names
+/// after the `#`.
+fn is_reserved_name(ident: &str) -> bool {
+    ident.starts_with('#')
+}
+
+/// The version of the crate using the macro, bound once if the template uses `#pkg_version`.
+fn get_pkg_version_ident() -> Ident {
+    Ident::new("__ext_format_pkg_version", Span::call_site())
+}
+
+/// The counter behind `#flatindex`, shared by all groups nested in the outermost one.
+fn get_flat_index_ident() -> Ident {
+    Ident::new("__ext_format_flat_index", Span::call_site())
+}
+
+/// The number of iterations of the group at the given depth.
+fn get_count_ident(level: usize) -> Ident {
+    Ident::new(&format!("__ext_format_count_{}", level), Span::call_site())
+}
+
+/// Whether the current element is the `#first` or `#last` one of the group at the given depth.
+fn get_position_ident(position: &str, level: usize) -> Ident {
+    Ident::new(
+        &format!("__ext_format_{}_{}", position, level),
+        Span::call_site(),
+    )
+}
+
+/// Whether a variable is used anywhere within the tokens, including nested groups.
+fn uses_variable(tokens: &[QuoteToken], name: &str) -> bool {
+    tokens.iter().any(|token| match token {
+        QuoteToken::Variable(ident, _, _) => ident == name,
+        QuoteToken::Group(tokens, separator, _, modifiers) => {
+            uses_variable(tokens, name)
+                || separator
+                    .as_ref()
+                    .is_some_and(|separator| uses_variable(separator, name))
+                || modifiers.iter().any(|modifier| {
+                    matches!(modifier, GroupModifier::LastSeparator(separator) if uses_variable(separator, name))
+                })
+        }
+        QuoteToken::Conditional(_, _, _, tokens) => uses_variable(tokens, name),
+        _ => false,
+    })
+}
+
+/// The loop counter of the group at the given depth, starting at 0 for the outermost group.
+fn get_loop_index_ident(level: usize) -> Ident {
+    Ident::new(&format!("__ext_format_index_{}", level), Span::call_site())
+}
+
+/// The one-based `#number` of the current element of the group at the given depth.
+fn get_number_ident(level: usize) -> Ident {
+    Ident::new(&format!("__ext_format_number_{}", level), Span::call_site())
+}
+
+/// Resolves `#index` to the loop counter of the innermost enclosing group and `#indexN` to the
+/// one of the group at depth `N`. Returns `None` for regular variables.
+fn get_index_ident(ident: &str, depth: usize) -> CodegenResult<Option<Ident>> {
+    if !is_reserved_name(ident) {
+        return Ok(None);
+    }
+    if ident == "#pkg_version" {
+        return Ok(Some(get_pkg_version_ident()));
+    }
+    if depth == 0 {
+        return Err(match ident {
+            "#count" => "#count outside of repetitions requires a repetition to count".to_string(),
+            _ => format!("{} can only be used inside of a repetition", ident),
+        });
+    }
+    match ident {
+        "#flatindex" => return Ok(Some(get_flat_index_ident())),
+        "#count" => return Ok(Some(get_count_ident(depth - 1))),
+        "#first" | "#last" => return Ok(Some(get_position_ident(&ident[1..], depth - 1))),
+        _ => {}
+    }
+    // `#number` is the one-based counterpart of `#index`
+    let (prefix, level) = match ident.strip_prefix("#number") {
+        Some(level) => ("#number", level),
+        None => ("#index", &ident["#index".len()..]),
+    };
+    let level = match level {
+        "" => depth - 1,
+        level => level.parse().unwrap_or(usize::MAX),
+    };
+    if level >= depth {
+        let available = match depth {
+            1 => format!("only {}0 is", prefix),
+            depth => format!("only {}0 to {}{} are", prefix, prefix, depth - 1),
+        };
+        return Err(format!(
+            "{} refers to a repetition which does not enclose it, {} available here",
+            ident, available
+        ));
+    }
+    Ok(Some(match prefix {
+        "#number" => get_number_ident(level),
+        _ => get_loop_index_ident(level),
+    }))
+}
+
+/// The ident a variable is rendered from: its loop binding inside of groups, or the loop
+/// counter it refers to.
+fn get_variable_ident(
+    ident: &str,
+    mapping: &HashMap<String, String>,
+    depth: usize,
+) -> CodegenResult<Ident> {
+    let new_name = mapping.get(ident).map_or(ident, String::as_str);
+    Ok(get_index_ident(ident, depth)?.unwrap_or_else(|| Ident::new(new_name, Span::call_site())))
+}
+
+/// The name of the function `ext_format_filter!` defines for a filter.
+pub(crate) fn get_filter_ident(name: &str) -> Ident {
+    Ident::new(&format!("__ext_format_filter_{}", name), Span::call_site())
+}
+
+/// Generates an expression rendering an `Option` or `Result`, applying the remaining modifiers
+/// to the contained value. Missing values are handled according to the `on_missing` pragma.
+fn generate_optional_code(
+    value: TokenStream,
+    ident: &str,
+    modifiers: Vec<Modifier>,
+    in_group: bool,
+    pragmas: &Pragmas,
+) -> TokenStream {
+    let value_stream = generate_modifier_code(quote!(value), ident, modifiers, in_group, pragmas);
+    let missing_stream = match pragmas.on_missing {
+        OnMissing::Empty => quote!(String::new()),
+        OnMissing::Error => {
+            let message = format!("{} is missing", ident);
+            quote!(ext_format_missing!(#message))
+        }
+    };
+    quote!({
+        trait Optional {
+            type Value;
+            fn ext_format_optional(&self) -> Option<&Self::Value>;
+        }
+        impl<T> Optional for Option<T> {
+            type Value = T;
+            fn ext_format_optional(&self) -> Option<&T> {
+                self.as_ref()
+            }
+        }
+        impl<T, E> Optional for Result<T, E> {
+            type Value = T;
+            fn ext_format_optional(&self) -> Option<&T> {
+                self.as_ref().ok()
+            }
+        }
+        match (#value).ext_format_optional() {
+            Some(value) => #value_stream,
+            None => #missing_stream,
+        }
+    })
+}
+
+/// Generates an expression formatting `value` with `float_format` if it is a float and with
+/// `format` otherwise, which is decided by the type of the value at compile time.
+fn generate_float_format_code(
+    value: TokenStream,
+    float_format: String,
+    format: String,
+) -> TokenStream {
+    // Method resolution prefers `FloatFormat`, which needs no autoref, if its bound holds
+    quote!({
+        trait Float {}
+        impl Float for f32 {}
+        impl Float for f64 {}
+        impl<T: Float + ?Sized> Float for &T {}
+
+        struct Value<T>(T);
+
+        trait FloatFormat {
+            fn ext_format_render(&self) -> String;
+        }
+        impl<T: Float + std::fmt::Display> FloatFormat for Value<T> {
+            fn ext_format_render(&self) -> String {
+                format!(#float_format, self.0)
+            }
+        }
+
+        trait DisplayFormat {
+            fn ext_format_render(&self) -> String;
+        }
+        impl<T: std::fmt::Display> DisplayFormat for &Value<T> {
+            fn ext_format_render(&self) -> String {
+                format!(#format, self.0)
+            }
+        }
+
+        (&Value(&#value)).ext_format_render()
+    })
+}
+
+/// Builds the format string passed to `format!` for a format spec, e.g. `"{:0>3}"`.
+fn get_format_string(spec: &FormatSpec) -> String {
+    let mut format_string = String::from("{:");
+    if let Some(fill) = spec.fill {
+        format_string.push(fill);
+    }
+    if let Some(align) = &spec.align {
+        format_string.push(match align {
+            Align::Left => '<',
+            Align::Center => '^',
+            Align::Right => '>',
+        });
+    }
+    if spec.zero {
+        format_string.push('0');
+    }
+    if let Some(width) = spec.width {
+        format_string.push_str(&width.to_string());
+    }
+    if let Some(precision) = spec.precision {
+        format_string.push_str(&format!(".{}", precision));
+    }
+    format_string.push('}');
+    format_string
+}
+
+/// Builds the format string padding a value rendered with [`get_unpadded_modifier`] to its
+/// column, taking the width from the `width` argument, e.g. `"{:>width$}"`.
+fn get_column_format_string(spec: &FormatSpec) -> String {
+    let format_string = get_format_string(&FormatSpec {
+        zero: false,
+        precision: None,
+        ..spec.clone()
+    });
+    format!("{}width$}}", format_string.trim_end_matches('}'))
+}
+
+/// The part of a format spec aligned to its column which is applied before the value is padded,
+/// i.e. its precision.
+fn get_unpadded_modifier(spec: &FormatSpec) -> Modifier {
+    Modifier::Format(FormatSpec {
+        precision: spec.precision,
+        ..FormatSpec::default()
+    })
+}
+
+/// Generates an expression rendering a value aligned to its column like the modifiers do, but
+/// without padding it, so its length is the width it takes up in the column.
+fn generate_column_value_code(
+    value: TokenStream,
+    ident: &str,
+    modifiers: &[Modifier],
+    pragmas: &Pragmas,
+) -> TokenStream {
+    let mut unpadded_modifiers = vec![];
+    for modifier in modifiers {
+        match modifier {
+            Modifier::Raw => {}
+            Modifier::Format(spec) if is_column_modifier(modifier) => {
+                unpadded_modifiers.push(get_unpadded_modifier(spec));
+                break;
+            }
+            // `cell` truncates to the width it is padded to, which takes its maximum into account
+            Modifier::ZeroPad | Modifier::Cell(_) => break,
+            modifier => unpadded_modifiers.push(modifier.clone()),
+        }
+    }
+    if unpadded_modifiers.is_empty() {
+        return quote!(#value.to_string());
+    }
+    generate_modifier_code(value, ident, unpadded_modifiers, false, pragmas)
+}
+
+/// The temporary a repetition source is bound to before it is iterated.
+fn get_source_ident(ident: &str) -> Ident {
+    Ident::new(&format!("__ext_format_source_{}", ident), Span::call_site())
+}
+
+/// The expression a repetition iterates, which is a variable or a count like the `3` in `@3`.
+fn get_source_expression(variable: &str) -> TokenStream {
+    match variable.parse::<usize>() {
+        Ok(count) => {
+            let count = proc_macro2::Literal::usize_suffixed(count);
+            quote!(#count)
+        }
+        Err(_) => {
+            let ident = Ident::new(variable, Span::call_site());
+            quote!(#ident)
+        }
+    }
+}
+
+fn get_column_width_ident(ident: &str) -> Ident {
+    let name = match ident.strip_prefix('#') {
+        Some(name) => format!("__ext_format_reserved_width_{}", name),
+        None => format!("__ext_format_width_{}", ident),
     };
-    new_tokens.into()
+    Ident::new(&name, Span::call_site())
+}
+
+/// Format specs with an alignment but without a width, like `>`, pad to the column as well.
+fn is_column_modifier(modifier: &Modifier) -> bool {
+    matches!(
+        modifier,
+        Modifier::ZeroPad
+            | Modifier::Cell(_)
+            | Modifier::Format(FormatSpec {
+                align: Some(_),
+                width: None,
+                ..
+            })
+    )
+}
+
+/// Generates a pass over the collected elements of a group, which renders all variables aligned
+/// to their column, e.g. with `zero_pad`, to find the width of their widest value before the
+/// group is rendered. `loop_head_stream` is the head of the loop binding the variables of each
+/// element, `position_stream` binds their position.
+fn generate_column_width_code(
+    tokens: &[QuoteToken],
+    mapping: &HashMap<String, String>,
+    depth: usize,
+    loop_head_stream: &TokenStream,
+    position_stream: &TokenStream,
+    pragmas: &Pragmas,
+) -> CodegenResult<TokenStream> {
+    let mut aligned_idents = vec![];
+    for token in get_scope_tokens(tokens) {
+        if let QuoteToken::Variable(ident, _, modifiers) = token {
+            if modifiers.iter().any(is_column_modifier) && !aligned_idents.contains(&ident) {
+                aligned_idents.push(ident);
+            }
+        }
+    }
+    if aligned_idents.is_empty() {
+        return Ok(TokenStream::new());
+    }
+    let width_idents = aligned_idents
+        .into_iter()
+        .map(|ident| get_column_width_ident(ident));
+    let measure_stream = generate_column_measure_code(tokens, mapping, depth, pragmas)?;
+    Ok(quote!(
+        #(let mut #width_idents = 0;)*
+        #loop_head_stream {
+            #position_stream
+            #measure_stream
+        }
+    ))
+}
+
+/// Generates the code updating the column widths with the values of the current element. Like
+/// the body of the group, it binds destructured variables and only measures the bodies of
+/// conditionals which hold.
+fn generate_column_measure_code(
+    tokens: &[QuoteToken],
+    mapping: &HashMap<String, String>,
+    depth: usize,
+    pragmas: &Pragmas,
+) -> CodegenResult<TokenStream> {
+    let mut rust_tokens: Vec<TokenStream> = vec![];
+    for token in tokens {
+        match token {
+            QuoteToken::HiddenVariable(ident, pattern) => {
+                rust_tokens.push(generate_hidden_variable_code(
+                    ident.clone(),
+                    pattern.clone(),
+                    mapping,
+                ));
+            }
+            QuoteToken::Variable(ident, _, modifiers)
+                if modifiers.iter().any(is_column_modifier) =>
+            {
+                // The counter only advances while the group is rendered
+                if ident == "#flatindex" {
+                    return Err("#flatindex can not be aligned to its column".to_string());
+                }
+                let var_ident = get_variable_ident(ident, mapping, depth)?;
+                let width_ident = get_column_width_ident(ident);
+                let value_stream =
+                    generate_column_value_code(quote!(#var_ident), ident, modifiers, pragmas);
+                rust_tokens.push(quote!(
+                    #width_ident = #width_ident.max(#value_stream.chars().count());
+                ));
+            }
+            QuoteToken::Conditional(ident, negated, pattern, tokens) => {
+                let body_stream = generate_column_measure_code(tokens, mapping, depth, pragmas)?;
+                rust_tokens.push(generate_condition_code(
+                    ident,
+                    *negated,
+                    pattern.as_ref(),
+                    mapping,
+                    depth,
+                    body_stream,
+                )?);
+            }
+            _ => {}
+        }
+    }
+    Ok(TokenStream::from_iter(rust_tokens))
 }
 
 fn generate_hidden_variable_code(
     ident: String,
-    inner_ident: Option<String>,
-    mapping: &mut HashMap<String, String>,
+    inner_pattern: Option<Pattern>,
+    mapping: &HashMap<String, String>,
 ) -> TokenStream {
     let new_name = mapping.get(&ident).unwrap_or(&ident);
     let var_ident = Ident::new(new_name, Span::call_site());
-    if let Some(inner_ident) = inner_ident {
-        if inner_ident != "_" {
-            let inner_var_ident = Ident::new(&inner_ident, Span::call_site());
-            return quote!(
-                let #inner_var_ident = #var_ident;
-            );
+    match inner_pattern {
+        Some(Pattern::Ident(inner_ident)) if inner_ident == "_" => TokenStream::new(),
+        Some(pattern) => {
+            let pattern_stream = generate_pattern_code(&pattern);
+            quote!(
+                let #pattern_stream = &#var_ident;
+            )
         }
+        None => TokenStream::new(),
     }
-    TokenStream::new()
 }
 
+fn generate_pattern_code(pattern: &Pattern) -> TokenStream {
+    match pattern {
+        Pattern::Ident(ident) => {
+            let ident = Ident::new(ident, Span::call_site());
+            quote!(#ident)
+        }
+        Pattern::Tuple(patterns) => {
+            let patterns = patterns.iter().map(generate_pattern_code);
+            quote!((#(#patterns),*))
+        }
+    }
+}
+
+fn get_pattern_idents(pattern: &Pattern) -> Vec<&String> {
+    match pattern {
+        Pattern::Ident(ident) => vec![ident],
+        Pattern::Tuple(patterns) => patterns.iter().flat_map(get_pattern_idents).collect(),
+    }
+}
+
+/// Collects the tokens rendered in the scope of a group, including the bodies of conditionals,
+/// which are not repetitions on their own.
+fn get_scope_tokens(tokens: &[QuoteToken]) -> Vec<&QuoteToken> {
+    let mut scope_tokens = vec![];
+    for token in tokens {
+        scope_tokens.push(token);
+        if let QuoteToken::Conditional(_, _, _, tokens) = token {
+            scope_tokens.extend(get_scope_tokens(tokens));
+        }
+    }
+    scope_tokens
+}
+
+/// Collects the variables a group iterates over, together with the name of their loop binding.
+/// Destructured variables are bound to a generated name and destructured inside the loop body.
 fn get_variable_names(tokens: &[QuoteToken]) -> Vec<(String, String)> {
     let mut variables = vec![];
     let mut inner_variables = HashSet::new();
-    for token in tokens.iter() {
-        let (variable, inner) = match token {
-            QuoteToken::Variable(ref variable, ref inner) => (variable, inner),
-            QuoteToken::HiddenVariable(ref variable, ref inner) => (variable, inner),
+    for token in get_scope_tokens(tokens) {
+        let (variable, inner, visible) = match token {
+            QuoteToken::Variable(ref variable, ref inner, _) => (variable, inner, true),
+            QuoteToken::HiddenVariable(ref variable, ref inner) => (variable, inner, false),
+            // The condition is iterated as is, its pattern is only bound inside the conditional
+            QuoteToken::Conditional(ref variable, _, ref pattern, _) => {
+                if !inner_variables.contains(variable) && !is_reserved_name(variable) {
+                    let entry = (
+                        variable.clone(),
+                        "__ext_format_inner_".to_string() + variable,
+                    );
+                    if !variables.contains(&entry) {
+                        variables.push(entry);
+                    }
+                }
+                inner_variables.extend(pattern.iter().flat_map(get_pattern_idents));
+                continue;
+            }
             _ => continue,
         };
+        if is_reserved_name(variable) {
+            continue;
+        }
         if !inner_variables.contains(variable) {
-            if let Some(inner) = inner {
-                inner_variables.insert(inner);
-                variables.push((variable.clone(), inner.clone()))
-            } else {
-                let inner_name = "__ext_format_inner_".to_string() + variable;
-                variables.push((variable.clone(), inner_name))
+            let default_name = "__ext_format_inner_".to_string() + variable;
+            let inner_name = match inner {
+                // Visible variables still need a binding to be printed
+                Some(Pattern::Ident(inner)) if inner == "_" && visible => default_name,
+                Some(Pattern::Ident(inner)) if inner == "_" => {
+                    // A hidden `_` only drives the iteration, which any other use already does
+                    if variables.iter().any(|(source, _)| source == variable) {
+                        continue;
+                    }
+                    inner.clone()
+                }
+                Some(Pattern::Ident(inner)) => {
+                    inner_variables.insert(inner);
+                    inner.clone()
+                }
+                Some(pattern) => {
+                    inner_variables.extend(get_pattern_idents(pattern));
+                    default_name
+                }
+                None => default_name,
+            };
+            // A hidden `_` seen before is replaced by the binding of this use
+            if let Some(discarded) = variables
+                .iter_mut()
+                .find(|(source, inner)| source == variable && inner == "_")
+            {
+                discarded.1 = inner_name;
+                continue;
+            }
+            let entry = (variable.clone(), inner_name);
+            if !variables.contains(&entry) {
+                variables.push(entry)
             }
         }
     }
     variables
 }
 
-fn generate_group_code(tokens: Vec<QuoteToken>, separator: Option<String>) -> TokenStream {
+/// Finds an inner ident which is bound to different variables within the same group, or which
+/// is bound again by a nested group, as the generated code would silently shadow one binding.
+fn find_duplicate_binding(tokens: &[QuoteToken], enclosing: &HashSet<String>) -> Option<String> {
+    for token in tokens {
+        let QuoteToken::Group(tokens, _, _, _) = token else {
+            continue;
+        };
+        let mut bound = enclosing.clone();
+        let mut sources = HashMap::new();
+        for token in tokens {
+            let (variable, pattern) = match token {
+                QuoteToken::Variable(variable, Some(pattern), _) => (variable, pattern),
+                QuoteToken::HiddenVariable(variable, Some(pattern)) => (variable, pattern),
+                _ => continue,
+            };
+            for ident in get_pattern_idents(pattern) {
+                if ident == "_" {
+                    continue;
+                }
+                match sources.get(ident) {
+                    Some(source) if source == variable => {}
+                    Some(_) => return Some(ident.clone()),
+                    None if enclosing.contains(ident) => return Some(ident.clone()),
+                    None => {
+                        sources.insert(ident.clone(), variable.clone());
+                        bound.insert(ident.clone());
+                    }
+                }
+            }
+        }
+        if let Some(ident) = find_duplicate_binding(tokens, &bound) {
+            return Some(ident);
+        }
+    }
+    None
+}
+
+/// Generates the code evaluating the selection of `checkbox` or `radio` before the loop and the
+/// code prefixing each element with its marker. A closure is called with each element, any other
+/// expression is compared with the index of the element.
+fn generate_selection_code(
+    modifiers: &[GroupModifier],
+    index_ident: &Ident,
+) -> CodegenResult<(TokenStream, TokenStream)> {
+    let Some((name, selection, selected, unselected)) =
+        modifiers.iter().find_map(|modifier| match modifier {
+            GroupModifier::Checkbox(selection) => Some(("checkbox", selection, "[x] ", "[ ] ")),
+            GroupModifier::Radio(selection) => Some(("radio", selection, "(*) ", "( ) ")),
+            _ => None,
+        })
+    else {
+        return Ok((TokenStream::new(), TokenStream::new()));
+    };
+    let selection_expression = parse_modifier_expression(name, selection)?;
+    let selection_ident = Ident::new("__ext_format_selection", Span::call_site());
+    let (selection_stream, condition_stream) = if let syn::Expr::Closure(_) = selection_expression {
+        if modifiers.contains(&GroupModifier::IntoIter) {
+            return Err(format!("a {} closure can not be combined with $~", name));
+        }
+        // Passing the closure through a function lets the compiler infer its argument type
+        (
+            quote!({
+                fn selection<T, F: Fn(T) -> bool>(_: &[T], selection: F) -> F {
+                    selection
+                }
+                selection(&iterator, #selection_expression)
+            }),
+            quote!(#selection_ident(iterator[#index_ident])),
+        )
+    } else {
+        (
+            quote!(#selection_expression),
+            quote!(#index_ident == #selection_ident),
+        )
+    };
+    Ok((
+        quote!(let #selection_ident = #selection_stream;),
+        quote!(
+            if #condition_stream {
+                res.push_str(#selected);
+            } else {
+                res.push_str(#unselected);
+            }
+        ),
+    ))
+}
+
+/// Restricts the separator to consecutive elements with different keys for `separate_by`.
+/// Like a selection closure, the key closure is bound once before the loop.
+fn generate_separator_key_code(
+    modifiers: &[GroupModifier],
+    index_ident: &Ident,
+    element_stream: &TokenStream,
+    next_element_stream: &TokenStream,
+    separator_stream: TokenStream,
+) -> CodegenResult<(TokenStream, TokenStream)> {
+    let Some(key) = modifiers.iter().find_map(|modifier| match modifier {
+        GroupModifier::SeparateBy(key) => Some(key),
+        _ => None,
+    }) else {
+        return Ok((TokenStream::new(), separator_stream));
+    };
+    if modifiers.contains(&GroupModifier::IntoIter) {
+        return Err("separate_by can not be combined with $~".to_string());
+    }
+    let key_stream = parse_modifier_expression("separate_by", key)?;
+    let key_ident = Ident::new("__ext_format_separator_key", Span::call_site());
+    Ok((
+        quote!(
+            let #key_ident = {
+                fn key<T, K: PartialEq, F: Fn(T) -> K>(_: &[T], key: F) -> F {
+                    key
+                }
+                key(&iterator, #key_stream)
+            };
+        ),
+        quote!(
+            if #index_ident + 1 < iterator.len()
+                && #key_ident(#element_stream) != #key_ident(#next_element_stream)
+            {
+                #separator_stream
+            }
+        ),
+    ))
+}
+
+/// Joins the separator, without trailing whitespace, and the last separator for
+/// `#![oxford_comma]`, so `(, )( and )` separates the last two elements with `, and `.
+fn get_serial_separator(
+    mut separator: Vec<QuoteToken>,
+    last_separator: Vec<QuoteToken>,
+) -> Vec<QuoteToken> {
+    if let Some(QuoteToken::Literal(literal)) = separator.last_mut() {
+        literal.truncate(literal.trim_end().len());
+    }
+    separator.extend(last_separator);
+    separator
+}
+
+/// Parses the closure or expression passed to a group modifier like `group_by`, so an invalid one
+/// is reported at the template instead of where the generated code uses it.
+fn parse_modifier_expression(name: &str, expression: &str) -> CodegenResult<syn::Expr> {
+    syn::parse_str(expression)
+        .map_err(|error| format!("invalid {} argument `{}`: {}", name, expression, error))
+}
+
+/// Finds the variable destructured as a `(key, value)` pair that `sort_by_value` sorts by, and
+/// returns the fields leading to it in the zipped elements of the iterator.
+fn get_pair_source_fields(
+    tokens: &[QuoteToken],
+    variables: &[(String, String)],
+) -> CodegenResult<TokenStream> {
+    let mut pairs = vec![];
+    for token in get_scope_tokens(tokens) {
+        if let QuoteToken::Variable(variable, Some(Pattern::Tuple(patterns)), _)
+        | QuoteToken::HiddenVariable(variable, Some(Pattern::Tuple(patterns))) = token
+        {
+            if patterns.len() == 2 && !pairs.contains(&variable) {
+                pairs.push(variable);
+            }
+        }
+    }
+    let [pair] = pairs[..] else {
+        return Err(format!(
+            "sort_by_value requires exactly one variable destructured as a (key, value) pair, found {}",
+            pairs.len()
+        ));
+    };
+
+    // `fizip!` nests the elements of the variables to the left, like `((a, b), c)`
+    let position = variables
+        .iter()
+        .position(|(variable, _)| variable == pair)
+        .unwrap_or_default();
+    let depth = variables.len() - 1 - position;
+    let fields = std::iter::repeat_n(syn::Index::from(0), depth)
+        .chain((position > 0).then(|| syn::Index::from(1)));
+    Ok(quote!(#(.#fields)*))
+}
+
+fn generate_group_modifier_code(
+    modifier: GroupModifier,
+    pair_fields: &TokenStream,
+) -> CodegenResult<TokenStream> {
+    Ok(match modifier {
+        // Equal values are ordered by their key, as maps don't iterate in a stable order
+        GroupModifier::SortByValue(Order::Ascending) => quote!(
+            iterator.sort_by(|a, b| {
+                a #pair_fields.1.cmp(&b #pair_fields.1)
+                    .then_with(|| a #pair_fields.0.cmp(&b #pair_fields.0))
+            });
+        ),
+        GroupModifier::SortByValue(Order::Descending) => quote!(
+            iterator.sort_by(|a, b| {
+                b #pair_fields.1.cmp(&a #pair_fields.1)
+                    .then_with(|| a #pair_fields.0.cmp(&b #pair_fields.0))
+            });
+        ),
+        GroupModifier::Rev => quote!(
+            iterator.reverse();
+        ),
+        GroupModifier::GroupBy(key) => {
+            let key = parse_modifier_expression("group_by", &key)?;
+            quote!(
+                let mut iterator = {
+                    let key = #key;
+                    let mut groups = Vec::new();
+                    let mut indices = std::collections::HashMap::new();
+                    for element in iterator {
+                        let index = *indices.entry(key(element)).or_insert(groups.len());
+                        if index == groups.len() {
+                            groups.push((key(element), Vec::new()));
+                        }
+                        groups[index].1.push(element);
+                    }
+                    groups
+                };
+            )
+        }
+        GroupModifier::Transpose => quote!(
+            let mut iterator = {
+                let columns = iterator.iter().map(|row| row.len()).min().unwrap_or(0);
+                (0..columns)
+                    .map(|column| iterator.iter().map(|row| &row[column]).collect::<Vec<_>>())
+                    .collect::<Vec<_>>()
+            };
+        ),
+        // Handled when the iterator is created, by the loop and by the separator
+        GroupModifier::IntoIter
+        | GroupModifier::Checkbox(_)
+        | GroupModifier::Radio(_)
+        | GroupModifier::SeparateBy(_)
+        | GroupModifier::SkipEmpty
+        | GroupModifier::LastSeparator(_) => TokenStream::new(),
+    })
+}
+
+fn generate_group_code(
+    tokens: Vec<QuoteToken>,
+    separator: Option<Vec<QuoteToken>>,
+    fallback: Option<String>,
+    modifiers: Vec<GroupModifier>,
+    depth: usize,
+    pragmas: &Pragmas,
+) -> CodegenResult<(TokenStream, TokenStream)> {
     let variables = get_variable_names(&tokens);
+    let index_ident = get_loop_index_ident(depth);
+    let last_separator = modifiers.iter().find_map(|modifier| match modifier {
+        GroupModifier::LastSeparator(separator) => Some(separator.clone()),
+        _ => None,
+    });
+
+    let loop_tokens: Vec<_> = get_scope_tokens(&tokens)
+        .into_iter()
+        .chain(separator.iter().flatten())
+        .chain(last_separator.iter().flatten())
+        .collect();
+    let uses_loop_variable = |name: &str| {
+        loop_tokens.iter().any(|token| {
+            matches!(
+                token,
+                QuoteToken::Variable(ident, _, _) | QuoteToken::Conditional(ident, _, _, _)
+                    if ident == name
+            )
+        })
+    };
+    let count_stream = if uses_loop_variable("#count") {
+        let count_ident = get_count_ident(depth);
+        quote!(let #count_ident = iterator.len();)
+    } else {
+        TokenStream::new()
+    };
+    let uses_first = uses_loop_variable("#first");
+    let uses_last = uses_loop_variable("#last");
+    // Nested groups refer to the number of this group as `#numberN`
+    let number_stream =
+        if uses_loop_variable("#number") || uses_variable(&tokens, &format!("#number{}", depth)) {
+            let number_ident = get_number_ident(depth);
+            quote!(let #number_ident = #index_ident + 1;)
+        } else {
+            TokenStream::new()
+        };
+    let mut position_stream = number_stream.clone();
+    if uses_first {
+        let first_ident = get_position_ident("first", depth);
+        position_stream.extend(quote!(let #first_ident = #index_ident == 0;));
+    }
+    if uses_last {
+        let last_ident = get_position_ident("last", depth);
+        position_stream.extend(quote!(let #last_ident = #index_ident + 1 == iterator.len();));
+    }
+
+    // `flatindex` counts the iterations of the groups using it across all their repetitions,
+    // so the counter lives in the outermost group
+    let flat_index_ident = get_flat_index_ident();
+    let uses_flat_index = uses_variable(&tokens, "#flatindex");
+    let flat_index_stream = if depth == 0 && uses_flat_index {
+        quote!(let mut #flat_index_ident = 0usize;)
+    } else {
+        TokenStream::new()
+    };
+    let flat_index_increment_stream = if get_scope_tokens(&tokens)
+        .iter()
+        .any(|token| matches!(token, QuoteToken::Variable(ident, _, _) if ident == "#flatindex"))
+    {
+        quote!(#flat_index_ident += 1;)
+    } else {
+        TokenStream::new()
+    };
 
     let mut mapping = HashMap::new();
     let mut idents = vec![];
+    let mut source_idents = vec![];
     let mut inner_idents = vec![];
 
     for (variable, inner) in variables.iter() {
         mapping.insert(variable.clone(), inner.clone());
-        idents.push(Ident::new(&variable, Span::call_site()));
-        inner_idents.push(Ident::new(&inner, Span::call_site()));
+        idents.push(get_source_expression(variable));
+        source_idents.push(get_source_ident(variable));
+        inner_idents.push(Ident::new(inner, Span::call_site()));
     }
 
-    let token_stream: TokenStream = generate_inner_code(tokens, mapping).into();
+    for modifier in modifiers.iter() {
+        let name = match modifier {
+            GroupModifier::GroupBy(_) => "group_by",
+            GroupModifier::Transpose => "transpose",
+            _ => continue,
+        };
+        if variables.len() != 1 {
+            return Err(format!(
+                "{} requires exactly one variable iterated by the repetition, found {}",
+                name,
+                variables.len()
+            ));
+        }
+        if name == "group_by" && modifiers.contains(&GroupModifier::IntoIter) {
+            return Err("group_by can not be combined with $~".to_string());
+        }
+    }
 
-    let separator_stream = if let Some(separator) = separator {
-        quote!(
-            if i < iterator.len() - 1 {
-                res.push_str(#separator);
-            }
+    let pair_fields = if modifiers
+        .iter()
+        .any(|modifier| matches!(modifier, GroupModifier::SortByValue(_)))
+    {
+        get_pair_source_fields(&tokens, &variables)?
+    } else {
+        TokenStream::new()
+    };
+
+    let (selection_stream, marker_stream) = generate_selection_code(&modifiers, &index_ident)?;
+
+    // Variables are borrowed, unless they are explicitly consumed with `$~(...)*`.
+    // Binding them first gives `Vec`s, slices and arrays the same type to iterate.
+    let (source_stream, iter_stream) = if modifiers.contains(&GroupModifier::IntoIter) {
+        (quote!(#(let #source_idents = #idents;)*), quote!(into_iter))
+    } else {
+        (
+            quote!(#(let #source_idents = (&ExtFormatSource(&#idents)).ext_format_source();)*),
+            quote!(iter),
+        )
+    };
+
+    let loop_head_stream = quote!(
+        for (#index_ident, nested_tuple!(#(#inner_idents),*)) in iterator.iter().enumerate()
+    );
+    let column_width_stream = generate_column_width_code(
+        &tokens,
+        &mapping,
+        depth + 1,
+        &loop_head_stream,
+        &position_stream,
+        pragmas,
+    )?;
+    let token_stream = generate_inner_code(tokens, mapping, depth + 1, pragmas)?;
+
+    // Variables in the separator are not iterated, so they are evaluated like top level variables
+    let separator_stream = match (separator, last_separator) {
+        (Some(separator), None) => {
+            let separator_stream =
+                generate_inner_code(separator, HashMap::new(), depth + 1, pragmas)?;
+            quote!(
+                if #index_ident < iterator.len() - 1 {
+                    #separator_stream
+                }
+            )
+        }
+        (Some(separator), Some(last_separator)) => {
+            let separator_stream =
+                generate_inner_code(separator.clone(), HashMap::new(), depth + 1, pragmas)?;
+            let last_separator_stream = if pragmas.oxford_comma {
+                let serial_separator = get_serial_separator(separator, last_separator.clone());
+                let last_separator_stream =
+                    generate_inner_code(last_separator, HashMap::new(), depth + 1, pragmas)?;
+                let serial_separator_stream =
+                    generate_inner_code(serial_separator, HashMap::new(), depth + 1, pragmas)?;
+                // Two elements are joined by the last separator alone, as in `a and b`
+                quote!(
+                    if iterator.len() > 2 {
+                        #serial_separator_stream
+                    } else {
+                        #last_separator_stream
+                    }
+                )
+            } else {
+                generate_inner_code(last_separator, HashMap::new(), depth + 1, pragmas)?
+            };
+            quote!(
+                if #index_ident + 2 < iterator.len() {
+                    #separator_stream
+                } else if #index_ident + 2 == iterator.len() {
+                    #last_separator_stream
+                }
+            )
+        }
+        (None, _) => TokenStream::new(),
+    };
+    let skip_empty = modifiers.contains(&GroupModifier::SkipEmpty);
+    // With `skip_empty`, the separator loop iterates the segments, which refer to their element
+    let (element_stream, next_element_stream) = if skip_empty {
+        (
+            quote!(elements[iterator[#index_ident].0]),
+            quote!(elements[iterator[#index_ident + 1].0]),
         )
     } else {
-        TokenStream::new().into()
+        (
+            quote!(iterator[#index_ident]),
+            quote!(iterator[#index_ident + 1]),
+        )
     };
+    let (separator_key_stream, separator_stream) = generate_separator_key_code(
+        &modifiers,
+        &index_ident,
+        &element_stream,
+        &next_element_stream,
+        separator_stream,
+    )?;
 
-    quote!(
-        let mut iterator = fizip!(#(#idents.iter()),*).collect::<Vec<_>>();
-        if !iterator.is_empty() {
-            for (i, nested_tuple!(#(#inner_idents),*)) in iterator.iter().enumerate() {
+    // With `skip_empty`, the elements are rendered into segments first and the separator
+    // is emitted while writing the non-empty ones, indexed by their position among them
+    let body_stream = if skip_empty {
+        let flat_index_start_stream = if uses_flat_index {
+            quote!(#flat_index_ident)
+        } else {
+            quote!(())
+        };
+        let segment_stream = quote!({
+            let mut res = String::new();
+            #marker_stream
+            #token_stream
+            res
+        });
+        // `first` and `last` refer to the non-empty elements. Whether an element is the last one
+        // is only known once all are rendered, so the last segment is rendered again.
+        let mut skip_empty_position_stream = number_stream.clone();
+        let mut last_position_stream = number_stream.clone();
+        let mut last_segment_stream = TokenStream::new();
+        if uses_first {
+            let first_ident = get_position_ident("first", depth);
+            skip_empty_position_stream.extend(quote!(let #first_ident = segments.is_empty();));
+            last_position_stream.extend(quote!(let #first_ident = segments.len() == 1;));
+        }
+        if uses_last {
+            let last_ident = get_position_ident("last", depth);
+            skip_empty_position_stream.extend(quote!(let #last_ident = false;));
+            last_position_stream.extend(quote!(let #last_ident = true;));
+            // The counter of `flatindex` is reset to its value when the segment was rendered
+            let (save_stream, restore_stream) = if uses_flat_index {
+                (
+                    quote!(
+                        let flat_index_end = #flat_index_ident;
+                        #flat_index_ident = segments.last().unwrap().1;
+                    ),
+                    quote!(#flat_index_ident = flat_index_end;),
+                )
+            } else {
+                (TokenStream::new(), TokenStream::new())
+            };
+            last_segment_stream = quote!(
+                if let Some(#index_ident) = segments.last().map(|segment| segment.0) {
+                    let nested_tuple!(#(#inner_idents),*) = &iterator[#index_ident];
+                    #last_position_stream
+                    #save_stream
+                    let segment = #segment_stream;
+                    #restore_stream
+                    if segment.is_empty() {
+                        segments.pop();
+                    } else {
+                        segments.last_mut().unwrap().2 = segment;
+                    }
+                }
+            );
+        }
+        quote!(
+            let mut segments = Vec::new();
+            #loop_head_stream {
+                #skip_empty_position_stream
+                let flat_index_start = #flat_index_start_stream;
+                let segment = #segment_stream;
+                if !segment.is_empty() {
+                    segments.push((#index_ident, flat_index_start, segment));
+                }
+                #flat_index_increment_stream
+            }
+            #last_segment_stream
+            let elements = iterator;
+            let iterator = segments;
+            for (#index_ident, (_, _, segment)) in iterator.iter().enumerate() {
+                res.push_str(segment);
+                #separator_stream
+            }
+        )
+    } else {
+        quote!(
+            #loop_head_stream {
+                #position_stream
+                #marker_stream
                 #token_stream
                 #separator_stream
+                #flat_index_increment_stream
             }
-        };
-    )
-    .into()
+        )
+    };
+
+    let modifier_stream = modifiers
+        .into_iter()
+        .map(|modifier| generate_group_modifier_code(modifier, &pair_fields))
+        .collect::<CodegenResult<TokenStream>>()?;
+
+    let fallback_stream = if let Some(fallback) = fallback {
+        quote!(
+            else {
+                res.push_str(#fallback);
+            }
+        )
+    } else {
+        TokenStream::new()
+    };
+
+    let iterator_stream = quote!(
+        #source_stream
+        let mut iterator = fizip!(#(#source_idents.#iter_stream()),*).collect::<Vec<_>>();
+        #modifier_stream
+    );
+    let loop_stream = quote!(
+        #flat_index_stream
+        #count_stream
+        #column_width_stream
+        #selection_stream
+        #separator_key_stream
+        if !iterator.is_empty() {
+            #body_stream
+        } #fallback_stream;
+    );
+    Ok((iterator_stream, loop_stream))
 }
 
 #[cfg(test)]
 mod tests {
     use super::QuoteToken::*;
     use super::*;
-    use crate::util::unindent;
+    use ext_format_parser::{parse_template, unindent};
 
     #[test]
     fn test_generate_inner_code_literal() {
         let tokens = vec![Literal("Hello".to_string())];
-        let output = generate_inner_code(tokens, HashMap::new());
+        let output = generate_inner_code(tokens, HashMap::new(), 0, &Pragmas::default()).unwrap();
         let output_str = output.to_string();
 
         assert_eq!(output_str, r#"res . push_str ("Hello") ;"#);
@@ -197,20 +2100,36 @@ mod tests {
         let mut mapping = HashMap::new();
         mapping.insert("var".to_string(), "var_mapped".to_string());
 
-        let tokens = vec![Variable("var".to_string(), None)];
-        let output = generate_inner_code(tokens, mapping);
+        let tokens = vec![Variable("var".to_string(), None, vec![])];
+        let output = generate_inner_code(tokens, mapping, 0, &Pragmas::default()).unwrap();
+        let output_str = output.to_string();
+
+        assert_eq!(
+            output_str,
+            r#"write ! (res , "{}" , var_mapped) . unwrap () ;"#
+        );
+    }
+
+    #[test]
+    fn test_generate_inner_code_variable_with_inner_name() {
+        let tokens = vec![Variable(
+            "var".to_string(),
+            Some(Pattern::Ident("inner".to_string())),
+            vec![],
+        )];
+        let output = generate_inner_code(tokens, HashMap::new(), 0, &Pragmas::default()).unwrap();
         let output_str = output.to_string();
 
         assert_eq!(
             output_str,
-            r#"res . push_str (& var_mapped . to_string ()) ;"#
+            r#"let inner = & var ; write ! (res , "{}" , inner) . unwrap () ;"#
         );
     }
 
     #[test]
     fn test_generate_inner_code_hidden_variable() {
         let tokens = vec![HiddenVariable("var".to_string(), None)];
-        let output = generate_inner_code(tokens, HashMap::new());
+        let output = generate_inner_code(tokens, HashMap::new(), 0, &Pragmas::default()).unwrap();
         let output_str = output.to_string();
 
         assert_eq!(output_str, "");
@@ -223,22 +2142,27 @@ mod tests {
 
         let group_tokens = vec![
             Literal("Literal".to_string()),
-            Variable("var".to_string(), None),
+            Variable("var".to_string(), None, vec![]),
         ];
 
-        let tokens = vec![Group(group_tokens, Some(",".to_string()))];
+        let tokens = vec![Group(
+            group_tokens,
+            Some(vec![Literal(",".to_string())]),
+            None,
+            vec![],
+        )];
 
-        let output = generate_inner_code(tokens, mapping);
+        let output = generate_inner_code(tokens, mapping, 0, &Pragmas::default()).unwrap();
         let output_str = output.to_string();
 
         let expected = unindent(
             r#"
-            let mut iterator = fizip ! (var . iter ()) . collect :: < Vec < _ >> () ;
+            { let __ext_format_source_var = (& ExtFormatSource (& var)) . ext_format_source () ; let mut iterator = fizip ! (__ext_format_source_var . iter ()) . collect :: < Vec < _ >> () ;
             @ if ! iterator . is_empty () { 
-            @for (i , nested_tuple ! (__ext_format_inner_var)) in iterator . iter () . enumerate () {
+            @for (__ext_format_index_0 , nested_tuple ! (__ext_format_inner_var)) in iterator . iter () . enumerate () {
             @ res . push_str ("Literal") ;
-            @ res . push_str (& __ext_format_inner_var . to_string ()) ;
-            @ if i < iterator . len () - 1 { res . push_str (",") ; } } } ;
+            @ write ! (res , "{}" , __ext_format_inner_var) . unwrap () ;
+            @ if __ext_format_index_0 < iterator . len () - 1 { res . push_str (",") ; } } } ; }
         "#,
         ).trim().replace("\n@", "");
 
@@ -252,21 +2176,48 @@ mod tests {
 
         let group_tokens = vec![
             Literal("Literal".to_string()),
-            Variable("var".to_string(), None),
+            Variable("var".to_string(), None, vec![]),
         ];
 
-        let tokens = vec![Group(group_tokens, None)];
+        let tokens = vec![Group(group_tokens, None, None, vec![])];
 
-        let output = generate_inner_code(tokens, mapping);
+        let output = generate_inner_code(tokens, mapping, 0, &Pragmas::default()).unwrap();
         let output_str = output.to_string();
 
         let expected = unindent(
             r#"
-            let mut iterator = fizip ! (var . iter ()) . collect :: < Vec < _ >> () ;
+            { let __ext_format_source_var = (& ExtFormatSource (& var)) . ext_format_source () ; let mut iterator = fizip ! (__ext_format_source_var . iter ()) . collect :: < Vec < _ >> () ;
             @ if ! iterator . is_empty () { 
-            @for (i , nested_tuple ! (__ext_format_inner_var)) in iterator . iter () . enumerate () {
+            @for (__ext_format_index_0 , nested_tuple ! (__ext_format_inner_var)) in iterator . iter () . enumerate () {
             @ res . push_str ("Literal") ;
-            @ res . push_str (& __ext_format_inner_var . to_string ()) ; } } ;
+            @ write ! (res , "{}" , __ext_format_inner_var) . unwrap () ; } } ; }
+        "#,
+        ).trim().replace("\n@", "");
+
+        assert_eq!(output_str, expected);
+    }
+
+    #[test]
+    fn test_generate_inner_code_group_with_fallback() {
+        let group_tokens = vec![Variable("var".to_string(), None, vec![])];
+
+        let tokens = vec![Group(
+            group_tokens,
+            None,
+            Some("(none)".to_string()),
+            vec![],
+        )];
+
+        let output = generate_inner_code(tokens, HashMap::new(), 0, &Pragmas::default()).unwrap();
+        let output_str = output.to_string();
+
+        let expected = unindent(
+            r#"
+            { let __ext_format_source_var = (& ExtFormatSource (& var)) . ext_format_source () ; let mut iterator = fizip ! (__ext_format_source_var . iter ()) . collect :: < Vec < _ >> () ;
+            @ if ! iterator . is_empty () { 
+            @for (__ext_format_index_0 , nested_tuple ! (__ext_format_inner_var)) in iterator . iter () . enumerate () {
+            @ write ! (res , "{}" , __ext_format_inner_var) . unwrap () ; } }
+            @ else { res . push_str ("(none)") ; } ; }
         "#,
         ).trim().replace("\n@", "");
 
@@ -282,22 +2233,206 @@ mod tests {
         let group = Group(
             vec![
                 Literal("A".to_string()),
-                Variable("var1".to_string(), Some("mapped_var1".to_string())),
-                HiddenVariable("hidden_var".to_string(), Some("_".to_string())),
+                Variable(
+                    "var1".to_string(),
+                    Some(Pattern::Ident("mapped_var1".to_string())),
+                    vec![],
+                ),
+                HiddenVariable(
+                    "hidden_var".to_string(),
+                    Some(Pattern::Ident("_".to_string())),
+                ),
+            ],
+            Some(vec![Literal(", ".to_string())]),
+            None,
+            vec![],
+        );
+
+        let output = generate_inner_code(vec![group], mapping, 0, &Pragmas::default()).unwrap();
+        let output_str = output.to_string();
+
+        let expected = unindent(
+            r#"
+            { let __ext_format_source_var1 = (& ExtFormatSource (& var1)) . ext_format_source () ; let __ext_format_source_hidden_var = (& ExtFormatSource (& hidden_var)) . ext_format_source () ; let mut iterator = fizip ! (__ext_format_source_var1 . iter () , __ext_format_source_hidden_var . iter ()) . collect :: < Vec < _ >> () ;
+            @ if ! iterator . is_empty () { for (__ext_format_index_0 , nested_tuple ! (mapped_var1 , _)) in iterator . iter () . enumerate () { res . push_str ("A") ;
+            @ let mapped_var1 = & mapped_var1 ;
+            @ write ! (res , "{}" , mapped_var1) . unwrap () ;
+            @ if __ext_format_index_0 < iterator . len () - 1 { res . push_str (", ") ; } } } ; }"#,
+        ).trim().replace("\n@", "");
+
+        assert_eq!(output_str, expected);
+    }
+
+    #[test]
+    fn test_get_index_ident() {
+        let get = |ident: &str, depth: usize| {
+            get_index_ident(ident, depth)
+                .unwrap()
+                .map(|ident| ident.to_string())
+        };
+
+        assert_eq!(get("index", 0), None);
+        assert_eq!(get("index", 2), None);
+        assert_eq!(get("#index", 2), Some("__ext_format_index_1".to_string()));
+        assert_eq!(get("#index0", 2), Some("__ext_format_index_0".to_string()));
+        assert_eq!(get("#number", 2), Some("__ext_format_number_1".to_string()));
+        assert_eq!(
+            get("#number0", 2),
+            Some("__ext_format_number_0".to_string())
+        );
+        assert_eq!(get("count", 2), None);
+        assert_eq!(
+            get("#flatindex", 2),
+            Some("__ext_format_flat_index".to_string())
+        );
+        assert_eq!(get("#count", 2), Some("__ext_format_count_1".to_string()));
+        assert_eq!(get("#first", 1), Some("__ext_format_first_0".to_string()));
+        assert_eq!(get("#last", 3), Some("__ext_format_last_2".to_string()));
+    }
+
+    #[test]
+    fn test_get_counted_groups() {
+        let tokens = parse_template("$#count $($a)* $#count $($b)* $#count").unwrap();
+        let counted_groups = get_counted_groups(&tokens);
+
+        assert_eq!(counted_groups, HashMap::from([(0, 2), (4, 6), (8, 6)]));
+        assert!(get_counted_groups(&parse_template("$#count").unwrap()).is_empty());
+        assert!(get_counted_groups(&parse_template("$count $($a)*").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_get_variable_names_discard() {
+        let get = |source: &str| {
+            let tokens = parse_template(source).unwrap();
+            let QuoteToken::Group(tokens, _, _, _) = &tokens[0] else {
+                unreachable!()
+            };
+            get_variable_names(tokens)
+        };
+        let inner = |variable: &str| {
+            vec![(
+                variable.to_string(),
+                "__ext_format_inner_".to_string() + variable,
+            )]
+        };
+
+        assert_eq!(get("$(@{v:_} $v)*"), inner("v"));
+        assert_eq!(get("$($v @{v:_})*"), inner("v"));
+        assert_eq!(get("$(${v:_})*"), inner("v"));
+        assert_eq!(
+            get("$(@{v:_} ${v:x})*"),
+            vec![("v".to_string(), "x".to_string())]
+        );
+        assert_eq!(get("$(@{v:_})*"), vec![("v".to_string(), "_".to_string())]);
+    }
+
+    #[test]
+    fn test_get_index_ident_outside_of_repetition() {
+        assert_eq!(
+            get_index_ident("#index2", 2).unwrap_err(),
+            "#index2 refers to a repetition which does not enclose it, \
+            only #index0 to #index1 are available here"
+        );
+        assert_eq!(
+            get_index_ident("#index3", 1).unwrap_err(),
+            "#index3 refers to a repetition which does not enclose it, \
+            only #index0 is available here"
+        );
+        assert_eq!(
+            get_index_ident("#number1", 1).unwrap_err(),
+            "#number1 refers to a repetition which does not enclose it, \
+            only #number0 is available here"
+        );
+        assert_eq!(
+            get_index_ident("#first", 0).unwrap_err(),
+            "#first can only be used inside of a repetition"
+        );
+    }
+
+    #[test]
+    fn test_find_duplicate_binding() {
+        let find = |source: &str| {
+            find_duplicate_binding(&parse_template(source).unwrap(), &HashSet::new())
+        };
+
+        assert_eq!(find("$(@{a:x} $(@{b:x} $x)*)*"), Some("x".to_string()));
+        assert_eq!(find("$(@{a:x} @{b:(y, x)} $x)*"), Some("x".to_string()));
+        assert_eq!(find("$(${a:x} ${a:x})*"), None);
+        assert_eq!(find("$(@{a:x} $(@{x:y} $y)*)* $(@{b:x} $x)*"), None);
+        assert_eq!(find("${a:x} ${b:x}"), None);
+    }
+
+    #[test]
+    fn test_generate_code_duplicate_binding() {
+        let error = generate_code(
+            parse_template("$(@{a:x} $(@{b:x} $x)*)*").unwrap(),
+            vec![],
+            Pragmas::default(),
+        )
+        .unwrap_err();
+
+        assert_eq!(error, "x is bound more than once in a repetition");
+    }
+
+    #[test]
+    fn test_generate_code_group_with_sort_by_value() {
+        let group = Group(
+            vec![
+                HiddenVariable(
+                    "map".to_string(),
+                    Some(Pattern::Tuple(vec![
+                        Pattern::Ident("k".to_string()),
+                        Pattern::Ident("v".to_string()),
+                    ])),
+                ),
+                Variable("k".to_string(), None, vec![]),
             ],
-            Some(", ".to_string()),
+            None,
+            None,
+            vec![GroupModifier::SortByValue(Order::Descending)],
         );
 
-        let output = generate_inner_code(vec![group], mapping);
+        let output =
+            generate_inner_code(vec![group], HashMap::new(), 0, &Pragmas::default()).unwrap();
         let output_str = output.to_string();
 
         let expected = unindent(
             r#"
-            let mut iterator = fizip ! (var1 . iter () , hidden_var . iter ()) . collect :: < Vec < _ >> () ;
-            @ if ! iterator . is_empty () { for (i , nested_tuple ! (mapped_var1 , _)) in iterator . iter () . enumerate () { res . push_str ("A") ;
-            @ let mapped_var1 = mapped_var1 ;
-            @ res . push_str (& mapped_var1 . to_string ()) ;
-            @ if i < iterator . len () - 1 { res . push_str (", ") ; } } } ;"#,
+            { let __ext_format_source_map = (& ExtFormatSource (& map)) . ext_format_source () ; let mut iterator = fizip ! (__ext_format_source_map . iter ()) . collect :: < Vec < _ >> () ;
+            @ iterator . sort_by (| a , b | { b . 1 . cmp (& a . 1) . then_with (|| a . 0 . cmp (& b . 0)) }) ;
+            @ if ! iterator . is_empty () { 
+            @for (__ext_format_index_0 , nested_tuple ! (__ext_format_inner_map)) in iterator . iter () . enumerate () {
+            @ let (k , v) = & __ext_format_inner_map ;
+            @ write ! (res , "{}" , k) . unwrap () ; } } ; }
+        "#,
+        ).trim().replace("\n@", "");
+
+        assert_eq!(output_str, expected);
+    }
+
+    #[test]
+    fn test_generate_code_group_with_zero_pad() {
+        let group = Group(
+            vec![Variable("ids".to_string(), None, vec![Modifier::ZeroPad])],
+            None,
+            None,
+            vec![],
+        );
+
+        let output =
+            generate_inner_code(vec![group], HashMap::new(), 0, &Pragmas::default()).unwrap();
+        let output_str = output.to_string();
+
+        let expected = unindent(
+            r#"
+            { let __ext_format_source_ids = (& ExtFormatSource (& ids)) . ext_format_source () ; let mut iterator = fizip ! (__ext_format_source_ids . iter ()) . collect :: < Vec < _ >> () ;
+            @ let mut __ext_format_width_ids = 0 ;
+            @ for (__ext_format_index_0 , nested_tuple ! (__ext_format_inner_ids)) in iterator . iter () . enumerate () {
+            @ __ext_format_width_ids = __ext_format_width_ids . max (__ext_format_inner_ids . to_string () . chars () . count ()) ; }
+            @ if ! iterator . is_empty () { 
+            @for (__ext_format_index_0 , nested_tuple ! (__ext_format_inner_ids)) in iterator . iter () . enumerate () {
+            @ res . push_str (& format ! ("{:0width$}" , __ext_format_inner_ids , width = __ext_format_width_ids)) ; } } ; }
+        "#,
         ).trim().replace("\n@", "");
 
         assert_eq!(output_str, expected);
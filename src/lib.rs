@@ -24,6 +24,18 @@
 //! let output = ext_format!("Hello, $name!");
 //! ```
 //!
+//! ### Inline Bindings
+//!
+//! Values that aren't stored in a local variable can be bound with trailing `name = expression` arguments:
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let a = 2;
+//! let b = 3;
+//! let output = ext_format!("sum: $total", total = a + b);
+//! // Output: "sum: 5"
+//! ```
+//!
 //! ### Binding new variable names
 //! Use `{name:new_name}` to bind a new name to a variable.
 //!
@@ -82,6 +94,15 @@
 //! //  line
 //! ```
 //!
+//! A number, or a variable holding an integer or a range, repeats the group that many times.
+//! Binding it with `@{3:i}` gives access to the current value.
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let output = ext_format!("$(@{3:i}$i)(, )*");
+//! // Output: "0, 1, 2"
+//! ```
+//!
 //! ### Repetition with named Iteration Variables
 //!
 //! Use `{name:new_name}` to bind a Name to a Variable.
@@ -93,6 +114,8 @@
 //! // Output: "Numbers: 1, 2, 3"
 //! ```
 //!
+//! Binding to `_` discards the name, for visible and hidden variables alike.
+//!
 //! ### Nested Repetitions
 //!
 //! Repetitions can contain other repetitions, acting like nested for-loops:
@@ -122,6 +145,276 @@
 //! // Bob 40
 //! ```
 //!
+//! ### Loop Indices
+//!
+//! Inside a repetition, `$#index` is the zero-based index of the current element. In nested repetitions, `$#index0` refers to the index of the outermost repetition, `$#index1` to the next one and so on. `$#number` and `$#numberN` are their one-based counterparts, e.g. for numbered lists. The nested indices were introduced as `$index0`, `$index1`, ..., which are plain variables now.
+//!
+//! The `#` marks names reserved for the state of the enclosing repetitions, so they never collide with variables of the caller: `$index` or `$count` are regular variables. The reserved names are `#index`, `#indexN`, `#number`, `#numberN`, `#flatindex`, `#count`, `#first`, `#last` and `#pkg_version`, any other name after a `#` is rejected.
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let matrix = vec![vec![1, 2], vec![3, 4]];
+//! let output = ext_format!("$(@{matrix:row}$(@{row:c}[$#index0,$#index1]=$c) *)(\n)*");
+//! // Output:
+//! // [0,0]=1 [0,1]=2
+//! // [1,0]=3 [1,1]=4
+//! ```
+//!
+//! `$#flatindex` counts the elements across all repetitions of the group using it, e.g. to number the cells of a grid sequentially. It was first spelled `$flatindex`:
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let grid = vec![vec!["a", "b"], vec!["c", "d"]];
+//! let output = ext_format!("$(@{grid:row}$(@{row:cell}$#flatindex=$cell) *)(\n)*");
+//! // Output:
+//! // 0=a 1=b
+//! // 2=c 3=d
+//! ```
+//!
+//! `$#count` is the number of iterations of the repetition. Outside of repetitions, it refers to the next repetition, or to the last one if none follows. Written `$count` when it was added, it took the `#` along with the other loop variables:
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let items = vec!["a", "b", "c"];
+//! let output = ext_format!("Found $#count items: $($#index/$#count=$items)(, )*");
+//! // Output: "Found 3 items: 0/3=a, 1/3=b, 2/3=c"
+//! ```
+//!
+//! `$#first` and `$#last` tell whether the current element is the first or the last one, e.g. to treat the head of a list differently with `$(if #first: ...)`. Templates using the earlier `$first` and `$last`, or `$(if first: ...)`, have to add the `#`:
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let columns = vec!["id", "name"];
+//! let output = ext_format!("$($(if #first: SELECT )$columns$(if #last: \\ FROM users))(, )*");
+//! // Output: "SELECT id, name FROM users"
+//! ```
+//!
+//! ### Fallback for empty Repetitions
+//!
+//! Use `{else:...}` directly after a repetition to output a fallback text if the repetition has no elements.
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let items: Vec<&str> = vec![];
+//! let output = ext_format!("Items: $($items)(, )*{else:(none)}");
+//! // Output: "Items: (none)"
+//! ```
+//!
+//! ### Runtime Separators
+//!
+//! Parenthesized separators can contain variables, which are evaluated at runtime.
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let values = vec![1, 2, 3];
+//! let sep = if cfg!(windows) { "\r\n" } else { "\n" };
+//! let output = ext_format!("$($values)($sep)*");
+//! ```
+//!
+//! ### Last Separator
+//!
+//! A second separator after the first one is used between the last two elements, e.g. to join lists in natural language. With the `#![oxford_comma]` pragma, the first separator without trailing whitespace is kept in front of it for lists of three or more elements.
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let items = vec!["a", "b", "c"];
+//! let output = ext_format!("$($items)(, )( and )*");
+//! // Output: "a, b and c"
+//!
+//! let output = ext_format!("#![oxford_comma]\n$($items)(, )( and )*");
+//! // Output: "a, b, and c"
+//! ```
+//!
+//! ### Destructuring Variables
+//!
+//! Use `{name:(a, b)}` on a hidden variable to destructure its elements, e.g. the key-value pairs of a map.
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! use std::collections::BTreeMap;
+//! let map = BTreeMap::from([("b", 2), ("a", 1)]);
+//! let output = ext_format!("$(@{map:(k, v)}$k=$v)(\n)*");
+//! // Output:
+//! // a=1
+//! // b=2
+//! ```
+//!
+//! ### Group Modifiers
+//!
+//! Modifiers at the start of a repetition change how it iterates. They are separated by spaces and terminated by a `:`, whitespace after the `:` is skipped.
+//!
+//! - `sort_by_value`: Iterates key-value pairs sorted by their value, and equal values by their key. Use `sort_by_value(desc)` for descending order. Requires exactly one variable destructured as a `(key, value)` pair, other variables are zipped along.
+//! - `rev`: Iterates the elements in reverse order, e.g. `$(rev: $numbers),*` for a countdown.
+//! - `group_by(|element| key)`: Partitions the elements by the key returned by the closure, which has to be `Eq + Hash`. The repetition iterates `(key, elements)` pairs in the order the keys first appear, and requires exactly one variable.
+//! - `separate_by(|element| key)`: Only emits the separator between consecutive elements whose keys differ, e.g. a blank line between the groups of a sorted list, while elements with the same key are written back to back. The key has to be `PartialEq`.
+//! - `skip_empty`: Renders each element up front and only emits the separator between non-empty ones, e.g. if a conditional hides some elements. Without it, `$($(if visible: $names))(,)*` can render `a,,c`. With it, `$#first` and `$#last` refer to the first and last non-empty element.
+//! - `transpose`: Iterates the columns of a nested collection like `Vec<Vec<T>>` instead of its rows, stopping at the shortest row. Requires exactly one variable.
+//! - `checkbox(selection)`, `radio(selection)`: Prefixes each element with `[x] ` or `(*) ` if it is selected and with `[ ] ` or `( ) ` otherwise. The selection is either the index of the selected element or a closure returning whether an element is selected.
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! use std::collections::HashMap;
+//! let scores = HashMap::from([("Alice", 30), ("Bob", 50), ("Carol", 40)]);
+//! let output = ext_format!("$(sort_by_value(desc): @{scores:(name, score)}$name: $score)(\n)*");
+//! // Output:
+//! // Bob: 50
+//! // Carol: 40
+//! // Alice: 30
+//! ```
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let items = vec![("fruit", "apple"), ("nut", "walnut"), ("fruit", "banana")];
+//! let output = ext_format!(
+//!     "$(group_by(|&(category, _)| category): @{items:(category, entries)}"
+//!     "$category: $(@{entries:(_, name)}$name)(, )*)(\n)*"
+//! );
+//! // Output:
+//! // fruit: apple, banana
+//! // nut: walnut
+//! ```
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let options = vec!["small", "medium", "large"];
+//! let output = ext_format!("$(radio(1): $options)(\n)*");
+//! // Output:
+//! // ( ) small
+//! // (*) medium
+//! // ( ) large
+//! ```
+//!
+//! ### Consuming Repetitions
+//!
+//! Repetitions borrow their variables, so they remain usable after the `ext_format!` call. Use `$~(...)` to consume the variables with `into_iter()` instead, e.g. for iterators.
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let lines = "first\nsecond".lines();
+//! let numbers = 1..3;
+//! let output = ext_format!("$~($numbers: $lines)(\n)*");
+//! // Output:
+//! // 1: first
+//! // 2: second
+//! ```
+//!
+//! ### Optional Values
+//!
+//! Use `${name?}` to render the value of an `Option` or `Result`. Further modifiers are applied to the contained value, e.g. `${name?|>8}`.
+//!
+//! By default, missing values render empty. The `#![on_missing=error]` pragma at the start of a template makes them panic instead, or fail formatting with `std::fmt::Error` in `ext_format_args!`, while `#![on_missing=empty]` keeps the default. The line break after a pragma is not part of the output.
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let title = Some("Dr.");
+//! let nickname: Option<&str> = None;
+//! let output = ext_format!("${title?} Alice (${nickname?})");
+//! // Output: "Dr. Alice ()"
+//! ```
+//!
+//! ### Conditionals
+//!
+//! `$(if name: ...)` renders its body once if `name` is `true` or `Some`, and nothing otherwise. The value of an `Option` can be bound with `$(if {name:value}: ...)`, and `$(if !name: ...)` negates the condition. Whitespace after the `:` is skipped, use `\ ` to start the body with a space:
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let verbose = true;
+//! let email = Some("alice@example.com");
+//! let output = ext_format!("Alice$(if {email:address}: \\ <$address>)$(if verbose: , admin)");
+//! // Output: "Alice <alice@example.com>, admin"
+//! ```
+//!
+//! ### Modifiers
+//!
+//! Modifiers change how a single variable is rendered. They are written as `${name|modifier}` and can be chained, e.g. `${name:new_name|first|second}`.
+//!
+//! - `zero_pad`: Zero-pads numbers to the width of the widest element of the repetition.
+//! - `>8`, `<8`, `^8`, `0>3`, `03`, `.2`: Aligns, pads and rounds values like Rust's format specs (`[[fill]align][0][width][.precision]`). Inside repetitions, an alignment without a width like `>` pads to the widest value of the column, e.g. `$(${#number|>} | $lines)(\n)*` for a listing with right-aligned line numbers. The values are measured as rendered by the modifiers before it and its precision, so `${amounts|money("$")|>}` lines up the formatted amounts.
+//! - `cell=N`: Pads values to the width of the widest element of the repetition, truncating them to at most `N` characters. Truncation never splits a character.
+//! - `hexdump`, `hexdump=N`: Renders bytes as a classic `offset  hex  |ascii|` hexdump with `N` (default 16) bytes per line.
+//! - `box`: Surrounds the value with a box-drawing frame sized to its widest line, e.g. for CLI banners.
+//! - `bar=N`, `bar(N, XY)`: Renders a ratio from `0.0` to `1.0` as an `N` characters wide progress bar like `[####----]`, using `X` and `Y` for the filled and the empty part.
+//! - `justify=N`: Wraps text to `N` columns and distributes spaces between words, so every line but the last of each paragraph is exactly `N` columns wide.
+//! - `wrap=N`, `wrap=N,hang=M`: Wraps text to `N` columns, indenting continuation lines by `M` spaces, e.g. for doc comments. Words longer than `N` columns get a line of their own.
+//! - `regex`: Escapes regex metacharacters, so the value can be embedded in a regex pattern as a literal.
+//! - `bit`: Renders a `bool` as `1` or `0`, e.g. for bitfields or CSV.
+//! - `csv`, `dsv("X")`: Quotes the value as a CSV field, or as a field separated by `X`, e.g. `dsv("\t")` for TSV. Fields containing the delimiter, quotes or line breaks are wrapped in quotes, doubling the quotes inside.
+//! - `money("X")`: Renders a number with two decimals and thousands separators, prefixed by the currency symbol `X`, e.g. `$1,234.50`. A width following it aligns the amount right, like `${amount|money("$")|12}`.
+//! - `diff("X")`: Prefixes each line of the value with the diff marker `X`, like `+`, `-` or ` `. A trailing line break is kept without starting another line.
+//! - `duration_auto`: Renders a count of nanoseconds (or a `Duration`) compactly with the fitting unit out of `ns`, `µs`, `ms` and `s`, e.g. `1.23µs` or `2.50ms`.
+//! - `raw`: Inserts the value unescaped, even if the template escapes values with `#![escape=html]`.
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let ids = vec![5, 42, 100];
+//! let output = ext_format!("$(${ids|zero_pad})(, )*");
+//! // Output: "005, 042, 100"
+//! ```
+//!
+//! ### Float Precision
+//!
+//! The `#![precision=N]` pragma renders all floats with `N` decimals, unless their format spec sets a precision of its own. Values of other types are not affected:
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let price = 4.5;
+//! let count = 3;
+//! let output = ext_format!("#![precision=2]\n$count x $price = ${price|.1}");
+//! // Output: "3 x 4.50 = 4.5"
+//! ```
+//!
+//! ### Custom Filters
+//!
+//! Register functions taking a `&str` and returning a `String` with `ext_format_filter!` to apply them like modifiers, e.g. `${name|shout}`. Any name which isn't a built-in modifier is applied as filter, so a misspelled modifier like `zeropad` fails to compile as an unregistered filter. Filters have to be registered in the scope of the `ext_format!` call. `filter(name)` applies a filter named like a built-in modifier, and `filter(path)` one registered in another module of the crate, e.g. `filter(text::shout)`.
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! # use ext_format::ext_format_filter;
+//! fn shout(value: &str) -> String {
+//!     value.to_uppercase() + "!"
+//! }
+//!
+//! ext_format_filter!(shout = shout, snake = |value: &str| value.replace(' ', "_"));
+//!
+//! let greeting = "hello world";
+//! let output = ext_format!("${greeting|snake|shout}");
+//! // Output: "HELLO_WORLD!"
+//! ```
+//!
+//! ### HTML Escaping
+//!
+//! The `#![escape=html]` pragma escapes `&`, `<`, `>`, `"` and `'` in all interpolated values, while the template itself is output as is. Trusted values can be inserted unescaped with the `raw` modifier:
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let title = "Tom & Jerry";
+//! let body = "<p>Hello</p>";
+//! let output = ext_format!("#![escape=html]\n<h1>$title</h1>${body|raw}");
+//! // Output: "<h1>Tom &amp; Jerry</h1><p>Hello</p>"
+//! ```
+//!
+//! ### Escaping
+//!
+//! Use `\$`, `\@` or `@@` to output a literal `$` or `@`. Templates also support the escapes `\n`, `\r`, `\t`, `\\` and `\xHH`, e.g. in raw strings. Consecutive `\xHH` escapes are decoded as UTF-8 bytes, so `\xc3\xa9` becomes `é`, and invalid UTF-8 is a compile error. Within groups and their separators, `\(` and `\)` output literal parentheses, e.g. `$($items)(\) => )*`. An escaped backslash before `n`, `r`, `t` or `x`, like `\\n`, outputs the backslash and the letter, in groups and separators alike.
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let name = "user";
+//! let output = ext_format!("$name@@example.com costs \\$5");
+//! // Output: "user@example.com costs $5"
+//! ```
+//!
+//! ### Custom Sigil
+//!
+//! Templates full of literal `$`, like shell scripts or Makefiles, can start interpolations with another character by passing `sigil = 'c'` before the template. `$` is then output as is, while the new sigil is escaped with a `\`:
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let target = "app";
+//! let output = ext_format!(sigil = '%', "%target: $(CC) -o %target main.c");
+//! // Output: "app: $(CC) -o app main.c"
+//! ```
+//!
 //! ### Multiline Strings
 //!
 //! For multiline strings, `ext_format_unindented` can be used to avoid leading whitespace:
@@ -166,54 +459,371 @@
 //! ```
 //! With the indentation of the resulting string depending on the indentation of the function itself.
 //!
+//! Indentation is measured in columns, with tabs advancing to the next multiple of 4 columns, so tab- and space-indented lines can be mixed.
+//!
+//! Lines starting with `\` followed by whitespace, at their very first column, keep their indentation. Only the `\` is removed, e.g. for preformatted text.
+//!
+//! ### Package Version
+//!
+//! `ext_format_with_pkg_version!` works like `ext_format!`, and additionally expands `$#pkg_version` to the version of the crate the macro is called from. The version is read from `CARGO_PKG_VERSION` when that crate is compiled, so it is fixed in the binary, and is empty when the variable isn't set. The other macros reject `$#pkg_version`. Like the loop variables, it is a reserved name, so a variable called `pkg_version` is rendered with `$pkg_version` as usual:
+//!
+//! ```rust
+//! # use ext_format::{ext_format, ext_format_with_pkg_version};
+//! let header = ext_format_with_pkg_version!("// generated by mytool v$#pkg_version");
+//! assert_eq!(header, format!("// generated by mytool v{}", env!("CARGO_PKG_VERSION")));
+//!
+//! let pkg_version = "1.2.3";
+//! let header = ext_format!("v$pkg_version");
+//! assert_eq!(header, "v1.2.3");
+//! ```
+//!
+//! ### Final Newline
+//!
+//! The `#![final_newline]` pragma makes the output end with exactly one line break, adding a missing one and removing extra ones, e.g. for generated source files:
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let name = "main";
+//! let output = ext_format!("#![final_newline]\nfn $name() {}\n\n");
+//! // Output: "fn main() {}\n"
+//! ```
+//!
+//! ### Lazy Formatting
+//!
+//! `ext_format_args!` works like `std::format_args!`: instead of allocating a `String`, it returns a value implementing `Display`, which writes directly to the formatter.
+//!
+//! ```rust
+//! # use ext_format::ext_format_args;
+//! use std::fmt;
+//!
+//! struct Greeting {
+//!     name: String,
+//! }
+//!
+//! impl fmt::Display for Greeting {
+//!     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+//!         let name = &self.name;
+//!         write!(f, "{}", ext_format_args!("Hi $name"))
+//!     }
+//! }
+//! ```
+//!
+//! ### Fallible Formatting
+//!
+//! `try_ext_format!` works like `ext_format!`, but returns a `Result<String, std::fmt::Error>`. If the `Display` implementation of a value fails, the error is returned instead of panicking. With `#![on_missing=error]`, missing `${name?}` values return the error as well, so `?` can short-circuit on them:
+//!
+//! ```rust
+//! # use ext_format::try_ext_format;
+//! use std::fmt;
+//!
+//! fn summary(names: &[&str], total: Option<u32>) -> Result<String, fmt::Error> {
+//!     let summary = try_ext_format!("#![on_missing=error]\n$($names)(, )*: ${total?}")?;
+//!     Ok(summary)
+//! }
+//!
+//! assert_eq!(summary(&["a", "b"], Some(3)), Ok("a, b: 3".to_string()));
+//! assert_eq!(summary(&["a", "b"], None), Err(fmt::Error));
+//! ```
+//!
+//! Modifiers render values with `to_string`, which still panics if their `Display` implementation fails.
+//!
+//! ### Adjacent Literals
+//!
+//! Long templates can be split into multiple adjacent string literals, which are concatenated before formatting:
+//!
+//! ```rust
+//! # use ext_format::ext_format;
+//! let x = 42;
+//! let output = ext_format!("line one\n" "line two $x");
+//! // Output:
+//! // line one
+//! // line two 42
+//! ```
+//!
+//! ## Parsing Templates
+//!
+//! The parser lives in the separate [`ext_format_parser`](ext_format_parser) crate, since proc-macro crates can only export macros. It can be used on its own to inspect the tokens of a template, e.g. when building a templating layer on top of `ext_format`:
+//!
+//! ```toml
+//! [dependencies]
+//! ext_format_parser = "0.1.1"
+//! ```
+//!
+//! ```rust
+//! use ext_format_parser::{parse_template, to_template, QuoteToken};
+//!
+//! let tokens = parse_template("Hello, $name!").unwrap();
+//! assert_eq!(tokens[0], QuoteToken::Literal("Hello, ".to_string()));
+//!
+//! // Tokens can be turned back into a template
+//! assert_eq!(to_template(&tokens), "Hello, ${name}!");
+//! ```
+//!
+//! Invalid templates are reported as `ParseError`, which the macros turn into compile errors.
+//!
 //! ## License
 //!
 //! This project is licensed under the MIT License. See the [LICENSE.md](LICENSE.md) file for details.
 
 extern crate core;
 
-use proc_macro::{TokenStream, TokenTree};
+use proc_macro::TokenStream;
+use proc_macro2::{token_stream, Ident, Punct, Spacing, Span, TokenTree};
+use quote::{quote, quote_spanned};
+use std::iter::Peekable;
+use syn::parse::{ParseStream, Parser};
 
 mod codegen;
-mod parse;
-mod util;
-
-use crate::codegen::generate_code;
-use crate::parse::parse;
-use crate::util::{unescape, unindent};
-
-fn process(source: String) -> TokenStream {
-    let unescaped_source = unescape(&source);
-    let tokens = parse(&unescaped_source);
-    let rust_code = generate_code(tokens);
-    rust_code.into()
+
+use crate::codegen::{
+    generate_code, generate_display_code, generate_pkg_version_code, generate_try_code,
+    get_filter_ident, CodegenResult,
+};
+use ext_format_parser::{parse_template_with_sigil, unindent, Pragmas, QuoteToken};
+
+type Generator = fn(
+    Vec<QuoteToken>,
+    Vec<(Ident, proc_macro2::TokenStream)>,
+    Pragmas,
+) -> CodegenResult<proc_macro2::TokenStream>;
+
+/// Errors in the template are reported at the template, as the parser has no finer spans.
+fn process(input: Input, generate: Generator) -> TokenStream {
+    let rust_code = parse_template_with_sigil(&input.template, input.sigil)
+        .map_err(|error| error.to_string())
+        .and_then(|(pragmas, tokens)| generate(tokens, input.bindings, pragmas));
+    match rust_code {
+        Ok(rust_code) => rust_code.into(),
+        Err(error) => {
+            let message = format!("invalid format: {}", error);
+            input_error(input.template_span, message).into()
+        }
+    }
 }
 
-fn get_string_literal(tokens: TokenStream) -> String {
-    let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+/// Invalid macro input, already turned into a `compile_error!` pointing at the offending tokens.
+type InputResult<T> = Result<T, proc_macro2::TokenStream>;
 
-    if let (Some(token), true) = (tokens.get(0), tokens.len() == 1) {
-        if let litrs::Literal::String(literal_string) = litrs::Literal::try_from(token).unwrap() {
-            return literal_string.value().to_string();
-        } else {
-            panic!("invalid format");
+fn input_error(span: Span, message: String) -> proc_macro2::TokenStream {
+    quote_spanned!(span=> compile_error!(#message))
+}
+
+/// The input of a formatting macro, like `ext_format!(sigil = '%', "%name", name = value)`.
+struct Input {
+    sigil: char,
+    template: String,
+    template_span: Span,
+    bindings: Vec<(Ident, proc_macro2::TokenStream)>,
+}
+
+/// Splits the input of the macro with the given name into its options, template and bindings.
+fn parse_input(input: TokenStream, name: &str) -> InputResult<Input> {
+    let mut tokens = proc_macro2::TokenStream::from(input).into_iter().peekable();
+    let sigil = get_sigil(&mut tokens)?;
+    let template_span = tokens.peek().map_or(Span::call_site(), TokenTree::span);
+    let template = get_string_literal(&mut tokens, name)?;
+    let bindings = get_bindings(&mut tokens)?;
+    Ok(Input {
+        sigil,
+        template,
+        template_span,
+        bindings,
+    })
+}
+
+/// Parses an optional `sigil = 'c',` before the template, which starts interpolations
+/// with `c` instead of `$`.
+fn get_sigil(tokens: &mut Peekable<token_stream::IntoIter>) -> InputResult<char> {
+    let name = match tokens.peek() {
+        Some(TokenTree::Ident(name)) if name == "sigil" => name.clone(),
+        _ => return Ok('$'),
+    };
+    tokens.next();
+    match tokens.next() {
+        Some(TokenTree::Punct(punct)) if punct.as_char() == '=' => {}
+        _ => {
+            let message = "invalid format: expected `=` after `sigil`".to_string();
+            return Err(input_error(name.span(), message));
+        }
+    }
+    let token = tokens.next();
+    let span = token.as_ref().map_or(name.span(), TokenTree::span);
+    let sigil = match token.as_ref().map(litrs::Literal::try_from) {
+        Some(Ok(litrs::Literal::Char(sigil))) => sigil.value(),
+        _ => {
+            let message = "invalid format: sigil expects a character literal".to_string();
+            return Err(input_error(span, message));
         }
-    } else {
-        panic!("invalid format");
     };
+    // These characters are part of the template syntax themselves
+    if !sigil.is_ascii_punctuation() || "\\@(){}*".contains(sigil) {
+        let message = format!("invalid format: `{}` can not be used as sigil", sigil);
+        return Err(input_error(span, message));
+    }
+    match tokens.next() {
+        Some(TokenTree::Punct(punct)) if punct.as_char() == ',' => Ok(sigil),
+        _ => {
+            let message = "invalid format: expected `,` after sigil".to_string();
+            Err(input_error(span, message))
+        }
+    }
+}
+
+/// Concatenates the values of all adjacent string literals at the start of the macro input,
+/// so long templates can be split like `ext_format!("line one\n" "line two")`.
+fn get_string_literal(
+    tokens: &mut Peekable<token_stream::IntoIter>,
+    name: &str,
+) -> InputResult<String> {
+    let mut literal = String::new();
+    let mut is_empty = true;
+    while let Some(token) = tokens.peek() {
+        match litrs::Literal::try_from(token) {
+            Ok(litrs::Literal::String(literal_string)) => {
+                literal.push_str(literal_string.value());
+                is_empty = false;
+                tokens.next();
+            }
+            _ if is_empty => {
+                let message = format!("{}! expects a string literal, found `{}`", name, token);
+                return Err(input_error(token.span(), message));
+            }
+            _ => break,
+        }
+    }
+    if is_empty {
+        let message = format!("{}! expects a string literal", name);
+        return Err(input_error(Span::call_site(), message));
+    }
+    Ok(literal)
+}
+
+/// Parses the `name = expr` bindings following the template, as in
+/// `ext_format!("sum: $total", total = a + b)`.
+fn get_bindings(
+    tokens: &mut Peekable<token_stream::IntoIter>,
+) -> InputResult<Vec<(Ident, proc_macro2::TokenStream)>> {
+    let mut bindings = vec![];
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Punct(punct) if punct.as_char() == ',' => {}
+            token => {
+                let message = format!("invalid format: expected `,`, found `{}`", token);
+                return Err(input_error(token.span(), message));
+            }
+        }
+        let name = match tokens.next() {
+            Some(TokenTree::Ident(name)) => name,
+            Some(token) => {
+                let message = format!("invalid format: expected binding name, found `{}`", token);
+                return Err(input_error(token.span(), message));
+            }
+            None => break,
+        };
+        match tokens.next() {
+            Some(TokenTree::Punct(punct)) if punct.as_char() == '=' => {}
+            _ => {
+                let message = format!("invalid format: expected `=` after `{}`", name);
+                return Err(input_error(name.span(), message));
+            }
+        }
+        let ends = match tokens.peek() {
+            Some(TokenTree::Punct(punct)) => punct.as_char() == ',',
+            token => token.is_none(),
+        };
+        if ends {
+            let message = format!("invalid format: expected expression for `{}`", name);
+            return Err(input_error(name.span(), message));
+        }
+        // Commas inside the expression, like in `foo::<A, B>()` or `|a, b| a + b`, don't end it
+        let parse_expression = |input: ParseStream| {
+            let expression: syn::Expr = input.parse()?;
+            let rest: proc_macro2::TokenStream = input.parse()?;
+            Ok((expression, rest))
+        };
+        let (expression, rest) = match parse_expression.parse2(tokens.by_ref().collect()) {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                let message = format!(
+                    "invalid format: invalid expression for `{}`: {}",
+                    name, error
+                );
+                return Err(input_error(error.span(), message));
+            }
+        };
+        *tokens = rest.into_iter().peekable();
+        bindings.push((name, quote!(#expression)));
+    }
+    Ok(bindings)
 }
 
 #[proc_macro]
 pub fn ext_format(input: TokenStream) -> TokenStream {
-    let literal = get_string_literal(input);
-    let res = process(literal);
-    res
+    match parse_input(input, "ext_format") {
+        Ok(input) => process(input, generate_code),
+        Err(error) => error.into(),
+    }
 }
 
 #[proc_macro]
 pub fn ext_format_unindented(input: TokenStream) -> TokenStream {
-    let literal = get_string_literal(input);
-    let unindented = unindent(&literal);
-    let res = process(unindented);
-    res
+    match parse_input(input, "ext_format_unindented") {
+        Ok(input) => process(
+            Input {
+                template: unindent(&input.template),
+                ..input
+            },
+            generate_code,
+        ),
+        Err(error) => error.into(),
+    }
+}
+
+#[proc_macro]
+pub fn ext_format_with_pkg_version(input: TokenStream) -> TokenStream {
+    match parse_input(input, "ext_format_with_pkg_version") {
+        Ok(input) => process(input, generate_pkg_version_code),
+        Err(error) => error.into(),
+    }
+}
+
+#[proc_macro]
+pub fn ext_format_args(input: TokenStream) -> TokenStream {
+    match parse_input(input, "ext_format_args") {
+        Ok(input) => process(input, generate_display_code),
+        Err(error) => error.into(),
+    }
+}
+
+#[proc_macro]
+pub fn try_ext_format(input: TokenStream) -> TokenStream {
+    match parse_input(input, "try_ext_format") {
+        Ok(input) => process(input, generate_try_code),
+        Err(error) => error.into(),
+    }
+}
+
+#[proc_macro]
+pub fn ext_format_filter(input: TokenStream) -> TokenStream {
+    // The filters are parsed like trailing bindings, which start with a comma
+    let mut tokens = proc_macro2::TokenStream::from_iter(
+        std::iter::once(TokenTree::Punct(Punct::new(',', Spacing::Alone)))
+            .chain(proc_macro2::TokenStream::from(input)),
+    )
+    .into_iter()
+    .peekable();
+    let filters = match get_bindings(&mut tokens) {
+        Ok(filters) => filters,
+        Err(error) => return error.into(),
+    };
+    let filters = filters.into_iter().map(|(name, filter)| {
+        let filter_ident = get_filter_ident(&name.to_string());
+        quote!(
+            #[allow(dead_code)]
+            pub(crate) fn #filter_ident(value: &str) -> String {
+                (#filter)(value)
+            }
+        )
+    });
+    proc_macro2::TokenStream::from_iter(filters).into()
 }
@@ -1,7 +1,15 @@
 #[cfg(test)]
+#[allow(clippy::useless_vec)]
 mod tests {
     use ext_format::ext_format;
+    use ext_format::ext_format_args;
+    use ext_format::ext_format_filter;
     use ext_format::ext_format_unindented;
+    use ext_format::ext_format_with_pkg_version;
+    use ext_format::try_ext_format;
+    use std::collections::BTreeMap;
+    use std::collections::HashMap;
+    use std::fmt;
 
     #[test]
     fn test_basic_interpolation() {
@@ -39,6 +47,44 @@ mod tests {
         assert_eq!(output, "Items:\n apple\n banana");
     }
 
+    #[test]
+    fn test_repetition_with_discarded_names() {
+        let values = vec!["a", "b"];
+        let output = ext_format!("$(@{values:_}$values)(, )*");
+        assert_eq!(output, "a, b");
+
+        let output = ext_format!("$($values@{values:_})(, )*");
+        assert_eq!(output, "a, b");
+
+        let output = ext_format!("$(${values:_}=$#index)(, )*");
+        assert_eq!(output, "a=0, b=1");
+    }
+
+    #[test]
+    fn test_repetition_with_count() {
+        let output = ext_format!("$(@3 line)(\n)*");
+        assert_eq!(output, " line\n line\n line");
+
+        let output = ext_format!("$(@{3:i}$i)(, )*");
+        assert_eq!(output, "0, 1, 2");
+
+        let n = 2usize;
+        let range = 1..4;
+        let output = ext_format!("$(@{n:i}$i) * | $(@{range:i}$i) *");
+        assert_eq!(output, "0 1 | 1 2 3");
+
+        let empty = 0u8;
+        let output = ext_format!("[$(@empty x)*]");
+        assert_eq!(output, "[]");
+    }
+
+    #[test]
+    fn test_nested_repetition_with_count() {
+        let bars = vec![("a", 3), ("b", 1)];
+        let output = ext_format!("$(@{bars:(label, width)}$label: $(@width#)*)(\n)*");
+        assert_eq!(output, "a: ###\nb: #");
+    }
+
     #[test]
     fn test_repetition_with_named_iteration_variables() {
         let numbers = vec![1, 2, 3];
@@ -61,6 +107,31 @@ mod tests {
         assert_eq!(output, "Profiles:\nAlice 30\nBob 40");
     }
 
+    #[test]
+    fn test_zipped_vec_and_array() {
+        let names: Vec<&str> = vec!["Alice", "Bob", "Carol"];
+        let scores: [u32; 3] = [90, 85, 77];
+        let output = ext_format!("$($names=$scores)(, )*");
+        assert_eq!(output, "Alice=90, Bob=85, Carol=77");
+
+        let output = ext_format!("$~($names=$scores)(, )*");
+        assert_eq!(output, "Alice=90, Bob=85, Carol=77");
+    }
+
+    #[test]
+    fn test_fallback_with_items() {
+        let items = vec!["apple", "banana"];
+        let output = ext_format!("Items: $($items)(, )*{else:(none)}");
+        assert_eq!(output, "Items: apple, banana");
+    }
+
+    #[test]
+    fn test_fallback_without_items() {
+        let items: Vec<&str> = vec![];
+        let output = ext_format!("Items: $($items)(, )*{else:(none)}");
+        assert_eq!(output, "Items: (none)");
+    }
+
     #[test]
     fn test_unindented_multiline_strings() {
         let matrix = vec![vec![1, 2, 3], vec![4, 5, 6], vec![7, 8, 9]];
@@ -73,4 +144,1407 @@ mod tests {
         );
         assert_eq!(output, "\nvoid func3() {\n    printf(\"1 2 3\");\n    printf(\"4 5 6\");\n    printf(\"7 8 9\");\n}\n        ");
     }
+
+    #[test]
+    fn test_unindented_crlf_strings() {
+        let items = vec!["a", "b"];
+        let output = ext_format_unindented!("\r\n    items:\r\n      $($items)(\r\n      )*\r\n");
+        assert_eq!(output, "\r\nitems:\r\n  a\r\n  b\r\n");
+    }
+
+    #[test]
+    fn test_sort_by_value_descending() {
+        let scores = HashMap::from([("Alice", 30), ("Bob", 50), ("Carol", 40)]);
+        let output =
+            ext_format!("$(sort_by_value(desc): @{scores:(name, score)}$name: $score)(\n)*");
+        assert_eq!(output, "Bob: 50\nCarol: 40\nAlice: 30");
+    }
+
+    #[test]
+    fn test_sort_by_value_ascending() {
+        let scores = HashMap::from([("Alice", 30), ("Bob", 50), ("Carol", 40)]);
+        let output = ext_format!("$(sort_by_value: @{scores:(name, score)}$name=$score)(, )*");
+        assert_eq!(output, "Alice=30, Carol=40, Bob=50");
+    }
+
+    #[test]
+    fn test_sort_by_value_with_zipped_variable() {
+        let scores = vec![("Bob", 50), ("Carol", 30), ("Alice", 30)];
+        let tags = vec!["c", "a", "b"];
+        let output =
+            ext_format!("$(sort_by_value: @{scores:(name, score)}$name=$score/$tags)(, )*");
+        assert_eq!(output, "Alice=30/b, Carol=30/a, Bob=50/c");
+        let output = ext_format!("$(sort_by_value(desc): $tags @{scores:(name, score)}$name)(, )*");
+        assert_eq!(output, "c Bob, b Alice, a Carol");
+    }
+
+    #[test]
+    fn test_zero_pad_to_widest_element() {
+        let ids = vec![5, 42, 100];
+        let output = ext_format!("$(${ids|zero_pad})(, )*");
+        assert_eq!(output, "005, 042, 100");
+    }
+
+    #[test]
+    fn test_zero_pad_bound_variable() {
+        let ids = vec![7, -12, 3];
+        let output = ext_format!("$(@{ids:id}#${id|zero_pad})(, )*");
+        assert_eq!(output, "#007, #-12, #003");
+    }
+
+    #[test]
+    fn test_zero_pad_destructured_variable() {
+        let pairs = vec![("a", 5), ("b", 120)];
+        let output = ext_format!("$(@{pairs:(k, v)}$k=${v|zero_pad})(, )*");
+        assert_eq!(output, "a=005, b=120");
+    }
+
+    #[test]
+    fn test_zero_pad_in_conditional() {
+        let values = vec![Some(7), None, Some(1024)];
+        let output = ext_format!("$($(if {values:v}: ${v|zero_pad}))(, )*");
+        assert_eq!(output, "0007, , 1024");
+    }
+
+    #[test]
+    fn test_zero_pad_consumed_variable() {
+        let values = vec![3, 14, 159];
+        let output = ext_format!("$~(${values|zero_pad})(, )*");
+        assert_eq!(output, "003, 014, 159");
+    }
+
+    #[test]
+    fn test_zero_pad_outside_of_repetition() {
+        let id = 42;
+        let output = ext_format!("#${id|zero_pad}");
+        assert_eq!(output, "#42");
+    }
+
+    #[test]
+    fn test_unindented_mixed_tabs_and_spaces() {
+        let output = ext_format_unindented!("\n\tfn main() {\n\t    body();\n    }\n");
+        assert_eq!(output, "\nfn main() {\n    body();\n}\n");
+    }
+
+    #[test]
+    fn test_unindented_keeps_marked_line() {
+        let code = "x = 1";
+        let output = ext_format_unindented!(
+            r"
+            <pre>
+\  $code
+            </pre>"
+        );
+        assert_eq!(output, "\n<pre>\n  x = 1\n</pre>");
+    }
+
+    #[test]
+    fn test_cell_truncates_multi_byte_characters() {
+        let names = vec!["Zoë", "Jürgen-Sébastien", "Ana"];
+        let ages = vec![30, 40, 50];
+        let output = ext_format!("$(|${names|cell=8}|$ages|)(\n)*");
+        assert_eq!(output, "|Zoë     |30|\n|Jürgen-S|40|\n|Ana     |50|");
+    }
+
+    #[test]
+    fn test_cell_shrinks_to_widest_element() {
+        let names = vec!["日本", "東京都"];
+        let output = ext_format!("$(|${names|cell=10}|)(\n)*");
+        assert_eq!(output, "|日本 |\n|東京都|");
+    }
+
+    #[test]
+    fn test_cell_outside_of_repetition() {
+        let name = "Jürgen-Sébastien";
+        let output = ext_format!("|${name|cell=10}|");
+        assert_eq!(output, "|Jürgen-Séb|");
+    }
+
+    #[test]
+    fn test_cell_with_floats() {
+        let prices = vec![1.5, 22.25];
+        let output = ext_format!("$([${prices|cell=8}])*");
+        assert_eq!(output, "[1.5  ][22.25]");
+    }
+
+    #[test]
+    fn test_cell_truncates_integers() {
+        let ids = vec![12345678, 42];
+        let output = ext_format!("$([${ids|cell=4}])*");
+        assert_eq!(output, "[1234][42  ]");
+    }
+
+    #[test]
+    fn test_two_adjacent_literals() {
+        let x = 42;
+        let output = ext_format!("line one\n" "line two $x");
+        assert_eq!(output, "line one\nline two 42");
+    }
+
+    #[test]
+    fn test_three_adjacent_literals() {
+        let numbers = vec![1, 2, 3];
+        let output = ext_format!("Numbers: " r"$($numbers)" "(, )*");
+        assert_eq!(output, "Numbers: 1, 2, 3");
+    }
+
+    #[test]
+    fn test_unindented_adjacent_literals() {
+        let name = "Alice";
+        let output = ext_format_unindented!("\n    Hello,\n" "        $name!\n");
+        assert_eq!(output, "\nHello,\n    Alice!\n");
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let bytes = b"Hello, World!\n\x00\xff".to_vec();
+        let output = ext_format!("${bytes|hexdump}");
+        assert_eq!(
+            output,
+            "00000000  48 65 6c 6c 6f 2c 20 57 6f 72 6c 64 21 0a 00 ff  |Hello, World!...|"
+        );
+    }
+
+    #[test]
+    fn test_hexdump_multiple_lines() {
+        let bytes = "0123456789".as_bytes();
+        let output = ext_format!("${bytes|hexdump=4}");
+        assert_eq!(
+            output,
+            "00000000  30 31 32 33  |0123|\n00000004  34 35 36 37  |4567|\n00000008  38 39        |89|"
+        );
+    }
+
+    #[test]
+    fn test_hexdump_in_repetition() {
+        let chunks = vec![vec![0x41u8, 0x42], vec![]];
+        let output = ext_format!("$(@{chunks:chunk}[${chunk|hexdump}])(\n)*");
+        assert_eq!(
+            output,
+            "[00000000  41 42                                            |AB|]\n[]"
+        );
+    }
+
+    #[test]
+    fn test_reusing_non_copy_variable() {
+        let s = String::from("text");
+        let output = ext_format!("$s $s");
+        assert_eq!(output, "text text");
+
+        let output = ext_format!("${s:a} $a $s");
+        assert_eq!(output, "text text text");
+        assert_eq!(s, "text");
+    }
+
+    #[test]
+    fn test_custom_sigil() {
+        let name = "world";
+        let items = vec![1, 2, 3];
+        let output = ext_format!(sigil = '%', "Hello, %name! $HOME %(%items)(, )* 100\\%");
+        assert_eq!(output, "Hello, world! $HOME 1, 2, 3 100%");
+
+        let output = ext_format!(sigil = '#', "echo \"${#name}\" #{name|>6}");
+        assert_eq!(output, "echo \"${world}\"  world");
+    }
+
+    #[test]
+    fn test_custom_sigil_with_bindings() {
+        let output = ext_format!(
+            sigil = '%',
+            "all: %(%targets)( )*\n\t$(CC) -o %{out} $^",
+            targets = vec!["main.o", "util.o"],
+            out = "app",
+        );
+        assert_eq!(output, "all: main.o util.o\n\t$(CC) -o app $^");
+    }
+
+    #[test]
+    fn test_trailing_scalar_binding() {
+        let a = 2;
+        let b = 3;
+        let output = ext_format!("sum: $total", total = a + b);
+        assert_eq!(output, "sum: 5");
+    }
+
+    #[test]
+    fn test_trailing_vec_binding() {
+        let a = 2;
+        let output = ext_format!(
+            "$($squares)(, )* $label",
+            squares = (1..=3).map(|x| x * x).collect::<Vec<_>>(),
+            label = format!("({} items)", a + 1),
+        );
+        assert_eq!(output, "1, 4, 9 (3 items)");
+    }
+
+    #[test]
+    fn test_trailing_binding_with_commas() {
+        let values = vec![1, 2, 3];
+        let output = ext_format!(
+            "$total $sizes",
+            total = values
+                .iter()
+                .copied()
+                .reduce(|digits, digit| digits * 10 + digit)
+                .unwrap(),
+            sizes = HashMap::<&str, usize>::from([("a", 1), ("b", 2)]).len(),
+        );
+        assert_eq!(output, "123 2");
+    }
+
+    #[test]
+    fn test_unindented_trailing_binding() {
+        let output = ext_format_unindented!(
+            "
+            name: $name",
+            name = "Alice"
+        );
+        assert_eq!(output, "\nname: Alice");
+    }
+
+    #[test]
+    fn test_bound_variable_is_borrowed() {
+        let name = String::from("Alice");
+        let pair = (String::from("Bob"), String::from("Carol"));
+        let output = ext_format!("${name:n} @{pair:(first, second)}$first $second");
+        assert_eq!(output, "Alice Bob Carol");
+        assert_eq!(name, "Alice");
+        assert_eq!(pair.0, "Bob");
+    }
+
+    #[test]
+    fn test_format_spec_alignment() {
+        let n = 42;
+        assert_eq!(ext_format!("${n|>4}"), "  42");
+        assert_eq!(ext_format!("${n|<4}|"), "42  |");
+        assert_eq!(ext_format!("${n|^6}|"), "  42  |");
+        assert_eq!(ext_format!("${n|*>5}"), "***42");
+    }
+
+    #[test]
+    fn test_format_spec_zero_padding() {
+        let n = 42;
+        let negative = -7;
+        assert_eq!(ext_format!("${n|0>3}"), "042");
+        assert_eq!(ext_format!("${negative|03}"), "-07");
+    }
+
+    #[test]
+    fn test_format_spec_in_repetition() {
+        let names = vec!["Alice", "Bob"];
+        let ages = vec![30, 4];
+        let output = ext_format!("$(${names|<6}|${ages|>3})(\n)*");
+        assert_eq!(output, "Alice | 30\nBob   |  4");
+    }
+
+    #[test]
+    fn test_format_spec_precision() {
+        let value = 1.23456;
+        assert_eq!(ext_format!("${value|.2}"), "1.23");
+        assert_eq!(ext_format!("${value|>8.3}"), "   1.235");
+    }
+
+    #[test]
+    fn test_precision_pragma() {
+        let price = 4.5;
+        let ratio = 0.123_f32;
+        let count = 3;
+        let name = "total";
+        let output = ext_format!("#![precision=2]\n$name: $count x $price = ${ratio|>6}");
+        assert_eq!(output, "total: 3 x 4.50 =   0.12");
+    }
+
+    #[test]
+    fn test_precision_pragma_overridden() {
+        let values = vec![1.0, 2.3];
+        let output = ext_format!("#![precision=2]\n$($values ${values:value|.1})(, )*");
+        assert_eq!(output, "1.00 1.0, 2.30 2.3");
+        let maybe = Some(0.5);
+        let output = ext_format!("#![precision=3]\n${maybe?}");
+        assert_eq!(output, "0.500");
+    }
+
+    #[test]
+    fn test_repetition_borrows_variables() {
+        let names = vec![String::from("Alice"), String::from("Bob")];
+        let output = ext_format!("$($names)(, )*");
+        assert_eq!(output, "Alice, Bob");
+        assert_eq!(names.len(), 2);
+        let output = ext_format!("$(@{names:name}$name)(, )*");
+        assert_eq!(output, "Alice, Bob");
+        assert_eq!(names, vec!["Alice", "Bob"]);
+    }
+
+    #[test]
+    fn test_by_value_repetition() {
+        let text = "first\nsecond";
+        let lines = text.lines();
+        let numbers = 1..3;
+        let output = ext_format!("$~($numbers: $lines)(\n)*");
+        assert_eq!(output, "1: first\n2: second");
+    }
+
+    #[test]
+    fn test_rev() {
+        let numbers = vec![1, 2, 3];
+        let output = ext_format!("$(rev: $numbers),*");
+        assert_eq!(output, "3,2,1");
+        assert_eq!(numbers, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_rev_with_fallback() {
+        let numbers: Vec<i32> = vec![];
+        let output = ext_format!("$(rev: $numbers)(, )*{else:liftoff}");
+        assert_eq!(output, "liftoff");
+    }
+
+    #[test]
+    fn test_box() {
+        let title = "Hello";
+        let output = ext_format!("${title|box}");
+        assert_eq!(output, "┌───────┐\n│ Hello │\n└───────┘");
+    }
+
+    #[test]
+    fn test_box_multiple_lines() {
+        let title = "ext_format\nv0.1 – ünïcode";
+        let output = ext_format!("${title|box}");
+        assert_eq!(
+            output,
+            "┌────────────────┐\n│ ext_format     │\n│ v0.1 – ünïcode │\n└────────────────┘"
+        );
+    }
+
+    #[test]
+    fn test_escaped_at() {
+        let name = "user";
+        assert_eq!(ext_format!("user\\@example.com"), "user@example.com");
+        assert_eq!(ext_format!("$name@@example.com"), "user@example.com");
+    }
+
+    #[test]
+    fn test_escaped_at_in_repetition() {
+        let users = vec!["alice", "bob"];
+        let output = ext_format!("$($users\\@example.com)(, )*");
+        assert_eq!(output, "alice@example.com, bob@example.com");
+        let output = ext_format!("$(@@$users)(, )*");
+        assert_eq!(output, "@alice, @bob");
+    }
+
+    #[test]
+    fn test_ext_format_args_in_write() {
+        struct Greeting {
+            name: String,
+            hobbies: Vec<&'static str>,
+        }
+
+        impl fmt::Display for Greeting {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let name = &self.name;
+                let hobbies = &self.hobbies;
+                write!(
+                    f,
+                    "{}",
+                    ext_format_args!("Hi $name, you like $($hobbies)(, )*")
+                )
+            }
+        }
+
+        let greeting = Greeting {
+            name: String::from("Alice"),
+            hobbies: vec!["chess", "climbing"],
+        };
+        assert_eq!(greeting.to_string(), "Hi Alice, you like chess, climbing");
+    }
+
+    #[test]
+    fn test_ext_format_args_with_binding() {
+        let numbers = vec![1, 2, 3];
+        let args = ext_format_args!("$($numbers),* = $sum", sum = numbers.iter().sum::<i32>());
+        assert_eq!(format!("[{}]", args), "[1,2,3 = 6]");
+        assert_eq!(args.to_string(), "1,2,3 = 6");
+    }
+
+    #[test]
+    fn test_ext_format_args_with_owned_bindings() {
+        let name = String::from("Alice");
+        let args = ext_format_args!(
+            "$title: $~($items),*",
+            title = name,
+            items = vec![String::from("a"), String::from("b")]
+        );
+        assert_eq!(args.to_string(), "Alice: a,b");
+        assert_eq!(format!("[{}]", args), "[Alice: a,b]");
+    }
+
+    #[test]
+    fn test_bar() {
+        let empty = 0.0;
+        let half = 0.5;
+        let full = 1.0;
+        assert_eq!(ext_format!("${empty|bar=20}"), "[--------------------]");
+        assert_eq!(ext_format!("${half|bar=20}"), "[##########----------]");
+        assert_eq!(ext_format!("${full|bar=20}"), "[####################]");
+    }
+
+    #[test]
+    fn test_bar_with_custom_characters() {
+        let ratios = vec![0.25f32, 1.5];
+        let output = ext_format!("$(${ratios|bar(8, █░)})(\n)*");
+        assert_eq!(output, "[██░░░░░░]\n[████████]");
+    }
+
+    #[test]
+    fn test_group_by() {
+        let items = vec![
+            ("fruit", "apple"),
+            ("vegetable", "carrot"),
+            ("fruit", "banana"),
+            ("vegetable", "leek"),
+            ("nut", "walnut"),
+        ];
+        let output = ext_format!(
+            "$(group_by(|&(category, _)| category): @{items:(category, entries)}"
+            "[$category]\n$(@{entries:(_, name)}- $name)(\n)*)(\n\n)*"
+        );
+        assert_eq!(
+            output,
+            "[fruit]\n- apple\n- banana\n\n[vegetable]\n- carrot\n- leek\n\n[nut]\n- walnut"
+        );
+    }
+
+    #[test]
+    fn test_group_by_computed_key() {
+        let words = vec!["a", "bb", "cc", "d", "eee"];
+        let output = ext_format!(
+            "$(group_by(|word: &&str| word.len()) rev: @{words:(len, group)}$len: $($group) *)(; )*"
+        );
+        assert_eq!(output, "3: eee; 2: bb cc; 1: a d");
+    }
+
+    #[test]
+    fn test_separate_by() {
+        let items = vec![
+            ("fruit", "apple"),
+            ("fruit", "banana"),
+            ("nut", "walnut"),
+            ("vegetable", "carrot"),
+            ("vegetable", "leek"),
+        ];
+        let output = ext_format!(
+            "$(separate_by(|&(category, _)| category): @{items:(category, name)}$category: $name\n)(\n)*"
+        );
+        assert_eq!(
+            output,
+            "fruit: apple\nfruit: banana\n\nnut: walnut\n\nvegetable: carrot\nvegetable: leek\n"
+        );
+    }
+
+    #[test]
+    fn test_separate_by_computed_key() {
+        let numbers = vec![1, 2, 11, 15, 23];
+        let output = ext_format!("$(separate_by(|n: &i32| n / 10): $numbers,)( )*");
+        assert_eq!(output, "1,2, 11,15, 23,");
+    }
+
+    #[test]
+    fn test_skip_empty() {
+        let names = vec!["a", "b", "c"];
+        let visible = vec![true, false, true];
+        let output = ext_format!("$($(if visible: $names))(,)*");
+        assert_eq!(output, "a,,c");
+
+        let output = ext_format!("$(skip_empty: $(if visible: $names))(,)*");
+        assert_eq!(output, "a,c");
+
+        let output = ext_format!("$(skip_empty: $(if visible: $names:$#index))(, )( and )*");
+        assert_eq!(output, "a:0 and c:2");
+    }
+
+    #[test]
+    fn test_skip_empty_first_and_last() {
+        let names = vec!["a", "b", "c", "d"];
+        let visible = vec![false, true, true, false];
+        let output =
+            ext_format!("$(skip_empty: $(if visible: $(if #first:[)$names$(if #last:])))(, )*");
+        assert_eq!(output, "[b, c]");
+
+        let output = ext_format!("$(skip_empty: $(if visible: $names$(if !#last:;)))*");
+        assert_eq!(output, "b;c");
+    }
+
+    #[test]
+    fn test_skip_empty_last_with_flat_index() {
+        let names = vec!["a", "b", "c"];
+        let visible = vec![true, true, false];
+        let output =
+            ext_format!("$(skip_empty: $(if visible: $#flatindex$names$(if #last:.)))(, )*");
+        assert_eq!(output, "0a, 1b.");
+    }
+
+    #[test]
+    fn test_skip_empty_with_separate_by() {
+        let numbers = vec![1, 2, 11, 15, 23];
+        let visible = vec![true, true, false, true, true];
+        let output = ext_format!(
+            "$(skip_empty separate_by(|(_, n): (&bool, &i32)| n / 10): $(if visible: $numbers,))( )*"
+        );
+        assert_eq!(output, "1,2, 15, 23,");
+    }
+
+    #[test]
+    fn test_skip_empty_with_only_empty_elements() {
+        let flags = vec![None::<u8>, None];
+        let output = ext_format!("[$(skip_empty: $(if {flags:flag}: $flag))(, )*{else:none}]");
+        assert_eq!(output, "[]");
+
+        let output =
+            ext_format_args!("[$(skip_empty: $(if {flags:flag}: $flag))(, )*]").to_string();
+        assert_eq!(output, "[]");
+    }
+
+    #[test]
+    fn test_repeated_binding_in_repetition() {
+        let numbers = vec![1, 2];
+        let output = ext_format!("$(${numbers:n}=${numbers:n})(, )*");
+        assert_eq!(output, "1=1, 2=2");
+    }
+
+    #[test]
+    fn test_conditional_bool() {
+        let verbose = true;
+        let output = ext_format!("done$(if verbose: , took 3s)");
+        assert_eq!(output, "done, took 3s");
+
+        let verbose = false;
+        let output = ext_format!("done$(if verbose: , took 3s)");
+        assert_eq!(output, "done");
+    }
+
+    #[test]
+    fn test_conditional_option() {
+        let email = Some("alice@example.com");
+        let output = ext_format!("Alice$(if {email:address}: \\ <$address>)");
+        assert_eq!(output, "Alice <alice@example.com>");
+
+        let email: Option<&str> = None;
+        let output = ext_format!("Alice$(if {email:address}: \\ <$address>)");
+        assert_eq!(output, "Alice");
+    }
+
+    #[test]
+    fn test_nested_conditional() {
+        let (a, b) = (true, Some(2));
+        let output = ext_format!("$(if a: a$(if {b:b}: b=$b))");
+        assert_eq!(output, "ab=2");
+
+        let (a, b) = (true, None::<u32>);
+        let output = ext_format!("$(if a: a$(if {b:b}: b=$b))");
+        assert_eq!(output, "a");
+    }
+
+    #[test]
+    fn test_negated_conditional() {
+        let verbose = false;
+        let email: Option<&str> = None;
+        let output = ext_format!("done$(if !verbose: , quietly)$(if !email: , no email)");
+        assert_eq!(output, "done, quietly, no email");
+
+        let items = vec!["a", "b", "c"];
+        let output = ext_format!("$($(if !#first: , )$items)*");
+        assert_eq!(output, "a, b, c");
+    }
+
+    #[test]
+    fn test_conditional_in_repetition() {
+        let names = vec!["Alice", "Bob", "Carol"];
+        let admins = vec![true, false, true];
+        let scores = vec![Some(3), None, Some(5)];
+        let output = ext_format!("$($names$(if admins: *)$(if {scores:(score)}: =$score))(, )*");
+        assert_eq!(output, "Alice*=3, Bob, Carol*=5");
+    }
+
+    #[test]
+    fn test_escape_html() {
+        let title = "Tom & Jerry's <show>";
+        let tags = vec!["\"quoted\"", "<b>"];
+        let output = ext_format!("#![escape=html]\n<h1>$title</h1>$(<i>${tags|>10}</i>)*");
+        assert_eq!(
+            output,
+            "<h1>Tom &amp; Jerry&#39;s &lt;show&gt;</h1><i>  &quot;quoted&quot;</i><i>       &lt;b&gt;</i>"
+        );
+    }
+
+    #[test]
+    fn test_escape_html_raw() {
+        let title = "<em>Hello</em>";
+        let body = "<p>Trusted & pre-escaped</p>";
+        let missing: Option<&str> = None;
+        let output = ext_format!(
+            "#![escape=html]\n<h1>$title</h1>${body|raw}${missing?|raw}",
+            missing = missing
+        );
+        assert_eq!(
+            output,
+            "<h1>&lt;em&gt;Hello&lt;/em&gt;</h1><p>Trusted & pre-escaped</p>"
+        );
+    }
+
+    #[test]
+    fn test_raw_without_escaping() {
+        let snippet = "<br>";
+        let output = ext_format!("${snippet|raw}");
+        assert_eq!(output, "<br>");
+    }
+
+    #[test]
+    fn test_escape_html_args() {
+        let name = "<Alice>";
+        let output = ext_format_args!("#![escape=html]\nHi $name").to_string();
+        assert_eq!(output, "Hi &lt;Alice&gt;");
+    }
+
+    #[test]
+    fn test_pkg_version() {
+        let output = ext_format_with_pkg_version!("// generated by v$#pkg_version");
+        let version = output.strip_prefix("// generated by v").unwrap();
+        let parts: Vec<&str> = version.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        assert!(parts
+            .iter()
+            .all(|part| part.chars().all(|ch| ch.is_ascii_digit())));
+        assert_eq!(version, env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn test_pkg_version_in_repetition() {
+        let files = vec!["a.rs", "b.rs"];
+        let output = ext_format_with_pkg_version!("$($files=$#pkg_version) *");
+        let version = env!("CARGO_PKG_VERSION");
+        assert_eq!(output, format!("a.rs={} b.rs={}", version, version));
+    }
+
+    #[test]
+    fn test_pkg_version_does_not_shadow_variables() {
+        let pkg_version = "9.9.9";
+        let output = ext_format!("v$pkg_version");
+        assert_eq!(output, "v9.9.9");
+
+        let output = ext_format!("v$pkg_version", pkg_version = "1.2.3");
+        assert_eq!(output, "v1.2.3");
+
+        let output = ext_format_with_pkg_version!("v$pkg_version/v$#pkg_version");
+        assert_eq!(output, format!("v9.9.9/v{}", env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn test_last_separator() {
+        let items = vec!["a", "b", "c"];
+        let output = ext_format!("$($items)(, )( and )*");
+        assert_eq!(output, "a, b and c");
+
+        let items = vec!["a", "b"];
+        let output = ext_format!("$($items)(, )( and )*");
+        assert_eq!(output, "a and b");
+
+        let items = vec!["a"];
+        let output = ext_format!("$($items)(, )( and )*");
+        assert_eq!(output, "a");
+    }
+
+    #[test]
+    fn test_last_separator_oxford_comma() {
+        let items = vec!["a", "b", "c"];
+        let output = ext_format!(
+            "#![oxford_comma]
+$($items)(, )( and )*"
+        );
+        assert_eq!(output, "a, b, and c");
+
+        let items = vec!["a", "b"];
+        let output = ext_format!(
+            "#![oxford_comma]
+$($items)(, )( and )*"
+        );
+        assert_eq!(output, "a and b");
+    }
+
+    #[test]
+    fn test_last_separator_with_variable() {
+        let conjunction = "or";
+        let items = vec![1, 2, 3, 4];
+        let output = ext_format_args!(
+            "#![oxford_comma]
+$($items)(, )( $conjunction )*"
+        )
+        .to_string();
+        assert_eq!(output, "1, 2, 3, or 4");
+    }
+
+    #[test]
+    fn test_final_newline_added() {
+        let name = "main";
+        let output = ext_format!("#![final_newline]\nfn $name() {}");
+        assert_eq!(output, "fn main() {}\n");
+    }
+
+    #[test]
+    fn test_final_newline_kept() {
+        let output = ext_format!("#![final_newline]\nfn main() {}\n");
+        assert_eq!(output, "fn main() {}\n");
+    }
+
+    #[test]
+    fn test_final_newline_trimmed() {
+        let lines = vec!["a\n", "b\r\n\n"];
+        let output = ext_format!("#![final_newline]\n$($lines)*\n\r\n");
+        assert_eq!(output, "a\nb\n");
+        let output = ext_format!("#![final_newline]\n\n\n");
+        assert_eq!(output, "\n");
+    }
+
+    #[test]
+    fn test_final_newline_args() {
+        let items = vec![1, 2];
+        let output = ext_format_args!("#![final_newline]\n$($items)(\n)*\n\n").to_string();
+        assert_eq!(output, "1\n2\n");
+    }
+
+    #[test]
+    fn test_optional_empty_on_missing() {
+        let nickname: Option<&str> = None;
+        let title = Some("Dr.");
+        let output = ext_format!("#![on_missing=empty]\n${title?} Alice (${nickname?})");
+        assert_eq!(output, "Dr. Alice ()");
+        let output = ext_format!("${title?|>5} Alice (${nickname?})");
+        assert_eq!(output, "  Dr. Alice ()");
+    }
+
+    #[test]
+    fn test_optional_in_repetition() {
+        let ages: Vec<Result<u32, String>> = vec![Ok(30), Err("unknown".to_string()), Ok(4)];
+        let output = ext_format!("$(${ages?})(, )*");
+        assert_eq!(output, "30, , 4");
+    }
+
+    #[test]
+    fn test_optional_error_on_missing() {
+        let title = Some("Dr.");
+        let output = ext_format!("#![on_missing=error]\n${title?} Alice");
+        assert_eq!(output, "Dr. Alice");
+    }
+
+    #[test]
+    #[should_panic(expected = "nickname is missing")]
+    fn test_optional_error_on_missing_panics() {
+        let nickname: Option<&str> = None;
+        ext_format_unindented!(
+            "
+            #![on_missing=error]
+            Alice (${nickname?})"
+        );
+    }
+
+    #[test]
+    fn test_optional_error_on_missing_args() {
+        use std::fmt::Write;
+
+        let nickname: Option<&str> = None;
+        let args = ext_format_args!("#![on_missing=error]\nAlice (${nickname?})");
+        let mut output = String::new();
+        assert!(write!(output, "{}", args).is_err());
+
+        let ages = vec![Some(30), None];
+        let args = ext_format_args!("#![on_missing=error]\n$(skip_empty: ${ages?})(, )*");
+        assert!(write!(output, "{}", args).is_err());
+    }
+
+    #[test]
+    fn test_btree_map_key_value_pairs() {
+        let map = BTreeMap::from([("b", 2), ("a", 1), ("c", 3)]);
+        let output = ext_format!("$(@{map:(k,v)}$k=$v)(\n)*");
+        assert_eq!(output, "a=1\nb=2\nc=3");
+    }
+
+    #[test]
+    fn test_nested_map_values() {
+        let map = BTreeMap::from([("odd", vec![1, 3]), ("even", vec![2, 4])]);
+        let output = ext_format!("$(@{map:(key, values)}$key: $($values)(, )*)(\n)*");
+        assert_eq!(output, "even: 2, 4\nodd: 1, 3");
+    }
+
+    #[test]
+    fn test_justify() {
+        let para = "The quick brown fox jumps over the lazy dog while the five boxing wizards \
+                    jump quickly and a pack of liquor jugs waits.";
+        let output = ext_format!("${para|justify=40}");
+        assert_eq!(
+            output,
+            "The  quick brown fox jumps over the lazy\n\
+             dog  while  the five boxing wizards jump\n\
+             quickly and a pack of liquor jugs waits."
+        );
+        for line in output.lines().take(2) {
+            assert_eq!(line.chars().count(), 40);
+        }
+    }
+
+    #[test]
+    fn test_justify_keeps_line_breaks_and_long_words() {
+        let text = "short line\nan extraordinarily long word";
+        let output = ext_format!("${text|justify=12}");
+        assert_eq!(output, "short line\nan\nextraordinarily\nlong word");
+    }
+
+    #[test]
+    fn test_wrap_with_hanging_indent() {
+        let text = "The quick brown fox jumps over the lazy dog while the five boxing wizards \
+                    jump quickly.";
+        let output = ext_format!("${text|wrap=30,hang=4}");
+        assert_eq!(
+            output,
+            "The quick brown fox jumps over\n    \
+             the lazy dog while the\n    \
+             five boxing wizards jump\n    \
+             quickly."
+        );
+        for line in output.lines() {
+            assert!(line.chars().count() <= 30);
+        }
+    }
+
+    #[test]
+    fn test_wrap_keeps_line_breaks() {
+        let text = "first paragraph here\n\nsecond one";
+        let output = ext_format!("${text|wrap=12}");
+        assert_eq!(output, "first\nparagraph\nhere\n\nsecond one");
+    }
+
+    #[test]
+    fn test_align_to_column() {
+        let names = vec!["a", "bbb", "cc"];
+        let output = ext_format!("$([${names|>}|${names|<}|${names|*^}])(\n)*");
+        assert_eq!(output, "[  a|a  |*a*]\n[bbb|bbb|bbb]\n[ cc|cc |cc*]");
+    }
+
+    #[test]
+    fn test_align_index_to_column() {
+        let items: Vec<_> = ('a'..='k').collect();
+        let output = ext_format!("$(${#index|>}=$items)( )*");
+        assert_eq!(
+            output,
+            " 0=a  1=b  2=c  3=d  4=e  5=f  6=g  7=h  8=i  9=j 10=k"
+        );
+
+        let rows = vec![vec!['a'; 10], vec!['b']];
+        let output = ext_format!("$(@{rows:row}$(${#number0|>}.${#number|<}$row)( )*)(\n)*");
+        assert_eq!(
+            output,
+            "1.1 a 1.2 a 1.3 a 1.4 a 1.5 a 1.6 a 1.7 a 1.8 a 1.9 a 1.10a\n2.1b"
+        );
+    }
+
+    #[test]
+    fn test_align_rendered_value_to_column() {
+        let amounts = vec![9.99, 1234.5];
+        let output = ext_format!("$(|${amounts|money(\"$\")|>}|)(\n)*");
+        assert_eq!(output, "|    $9.99|\n|$1,234.50|");
+
+        let values = vec![1.04, 22.46];
+        let output = ext_format!("$(|${values|>.1}|)(\n)*");
+        assert_eq!(output, "| 1.0|\n|22.5|");
+
+        let lines = vec!["abc", "de"];
+        let output = ext_format!("$(|${lines|>.1}|)(\n)*");
+        assert_eq!(output, "|a|\n|d|");
+
+        let prices = vec![1.5, 10.25];
+        let output = ext_format!("#![precision=2]\n$(|${prices|>}|)(\n)*");
+        assert_eq!(output, "| 1.50|\n|10.25|");
+
+        let offsets = vec![-5, 120];
+        let output = ext_format!("$(|${offsets|>0}|)(\n)*");
+        assert_eq!(output, "|-05|\n|120|");
+    }
+
+    #[test]
+    fn test_numbered_source_listing() {
+        let code = [
+            "fn main() {",
+            "    let mut total = 0;",
+            "    for i in 0..10 {",
+            "        total += i;",
+            "    }",
+            "",
+            "    // The sum of 0 to 9",
+            "    assert_eq!(total, 45);",
+            "    println!(\"{}\", total);",
+            "}",
+        ]
+        .join("\n");
+        let lines: Vec<_> = code.lines().collect();
+        let output = ext_format!("$(${#number|>} | $lines)(\n)*");
+        assert_eq!(
+            output,
+            " 1 | fn main() {\n \
+              2 |     let mut total = 0;\n \
+              3 |     for i in 0..10 {\n \
+              4 |         total += i;\n \
+              5 |     }\n \
+              6 | \n \
+              7 |     // The sum of 0 to 9\n \
+              8 |     assert_eq!(total, 45);\n \
+              9 |     println!(\"{}\", total);\n\
+             10 | }"
+        );
+    }
+
+    #[test]
+    fn test_display_values_with_io_write_in_scope() {
+        #[allow(unused_imports)]
+        use std::io::Write;
+
+        struct Point(i32, i32);
+
+        impl fmt::Display for Point {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "({}, {})", self.0, self.1)
+            }
+        }
+
+        let origin = Point(0, 0);
+        let points = vec![Point(1, 2), Point(3, 4)];
+        let output = ext_format!("$origin -> $($points)( -> )*");
+        assert_eq!(output, "(0, 0) -> (1, 2) -> (3, 4)");
+    }
+
+    #[test]
+    fn test_runtime_separator() {
+        let values = vec![1, 2, 3];
+        for (tabs, expected) in [(false, "1, 2, 3"), (true, "1\t2\t3")] {
+            let sep = if tabs { "\t" } else { ", " };
+            let output = ext_format!("$($values)($sep)*");
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn test_runtime_separator_with_literals() {
+        let values = vec!["a", "b", "c"];
+        let op = "+";
+        let output = ext_format!("$($values)( $op )*");
+        assert_eq!(output, "a + b + c");
+    }
+
+    fn shout(value: &str) -> String {
+        value.to_uppercase() + "!"
+    }
+
+    ext_format_filter!(shout = shout);
+
+    #[test]
+    fn test_registered_filter() {
+        let greeting = "hello";
+        let output = ext_format!("${greeting|shout}");
+        assert_eq!(output, "HELLO!");
+
+        let output = ext_format!("${greeting|filter(shout)}");
+        assert_eq!(output, "HELLO!");
+    }
+
+    #[test]
+    fn test_registered_filters_in_function_scope() {
+        ext_format_filter!(
+            reverse = |value: &str| value.chars().rev().collect(),
+            snake = |value: &str| value.replace(' ', "_"),
+        );
+        let names = vec!["ab c", "de"];
+        let output = ext_format!("$(${names|reverse|snake|filter(shout)})(, )*");
+        assert_eq!(output, "C_BA!, ED!");
+    }
+
+    mod text {
+        use ext_format::ext_format_filter;
+
+        ext_format_filter!(whisper = |value: &str| value.to_lowercase() + "...");
+    }
+
+    #[test]
+    fn test_registered_filter_in_other_module() {
+        let greeting = "HELLO";
+        let output = ext_format!("${greeting|filter(text::whisper)}");
+        assert_eq!(output, "hello...");
+    }
+
+    #[test]
+    fn test_regex() {
+        let literal = "a.b*c";
+        let output = ext_format!("^${literal|regex}\\$");
+        assert_eq!(output, r"^a\.b\*c$");
+    }
+
+    #[test]
+    fn test_regex_all_metacharacters() {
+        let literal = r".*+?()[]{}^$|\ -";
+        let output = ext_format!("${literal|regex}");
+        assert_eq!(output, r"\.\*\+\?\(\)\[\]\{\}\^\$\|\\ -");
+    }
+
+    #[test]
+    fn test_bit() {
+        let (enabled, disabled) = (true, false);
+        assert_eq!(ext_format!("${enabled|bit}${disabled|bit}"), "10");
+
+        let flags = vec![true, false, false, true];
+        let output = ext_format!("$(${flags|bit})(,)*");
+        assert_eq!(output, "1,0,0,1");
+    }
+
+    #[test]
+    fn test_csv() {
+        let fields = vec!["plain", "a,b", "say \"hi\"", "two\nlines"];
+        let output = ext_format!("$(${fields|csv}),*");
+        assert_eq!(output, "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\"");
+    }
+
+    #[test]
+    fn test_dsv_with_tab_delimiter() {
+        let fields = vec!["a,b", "c\td", "e"];
+        let output = ext_format!(r#"$(${fields|dsv("\t")})(\t)*"#);
+        assert_eq!(output, "a,b\t\"c\td\"\te");
+    }
+
+    #[test]
+    fn test_money() {
+        let amount = 1234.5;
+        assert_eq!(ext_format!("${amount|money(\"$\")}"), "$1,234.50");
+
+        let amounts = vec![0.5, -1234567.891, -0.001];
+        let output = ext_format!("$(${amounts|money(\"EUR \")})(, )*");
+        assert_eq!(output, "EUR 0.50, -EUR 1,234,567.89, EUR 0.00");
+
+        let cents = 120_000u32;
+        assert_eq!(ext_format!("${cents|money}"), "120,000.00");
+    }
+
+    #[test]
+    fn test_money_with_large_integers() {
+        let amount = 9_007_199_254_740_993u64;
+        assert_eq!(
+            ext_format!("${amount|money(\"$\")}"),
+            "$9,007,199,254,740,993.00"
+        );
+
+        let amounts = vec![i64::MIN, -9_007_199_254_740_993, 0];
+        let output = ext_format!("$(${amounts|money})(, )*");
+        assert_eq!(
+            output,
+            "-9,223,372,036,854,775,808.00, -9,007,199,254,740,993.00, 0.00"
+        );
+    }
+
+    #[test]
+    fn test_money_with_width() {
+        let prices = vec![9.99, 1234.5];
+        let output = ext_format!("$(|${prices|money(\"$\")|10}|)(\n)*");
+        assert_eq!(output, "|     $9.99|\n| $1,234.50|");
+
+        let price = 9.99;
+        assert_eq!(ext_format!("|${price|money(\"$\")|<8}|"), "|$9.99   |");
+    }
+
+    #[test]
+    fn test_diff() {
+        let removed = "fn main() {}";
+        let added = "fn main() {\n\n    run();\n}\n";
+        let output = ext_format!("@@@@ -1 +1,4 @@@@\n${removed|diff(\"-\")}\n${added|diff(\"+\")}");
+        assert_eq!(
+            output,
+            "@@ -1 +1,4 @@\n-fn main() {}\n+fn main() {\n+\n+    run();\n+}\n"
+        );
+
+        let empty = "";
+        assert_eq!(ext_format!("[${empty|diff(\"+\")}]"), "[]");
+    }
+
+    #[test]
+    fn test_duration_auto() {
+        let nanos: Vec<u64> = vec![
+            7,
+            999,
+            1_000,
+            1_234,
+            56_789,
+            999_999,
+            2_500_000,
+            120_000_000,
+        ];
+        let output = ext_format!("$(${nanos|duration_auto})(, )*");
+        assert_eq!(
+            output,
+            "7ns, 999ns, 1.00µs, 1.23µs, 56.8µs, 1.00ms, 2.50ms, 120ms"
+        );
+
+        let nanos: u128 = 3_600_000_000_000;
+        let output = ext_format!("${nanos|duration_auto}");
+        assert_eq!(output, "3600s");
+    }
+
+    #[test]
+    fn test_duration_auto_duration() {
+        let elapsed = std::time::Duration::from_micros(1_500);
+        let output = ext_format!("took ${elapsed|duration_auto|>8}");
+        assert_eq!(output, "took   1.50ms");
+    }
+
+    #[test]
+    fn test_index() {
+        let items = vec!["apple", "banana"];
+        let output = ext_format!("$(${#index|>2}. $items)(\n)*");
+        assert_eq!(output, " 0. apple\n 1. banana");
+    }
+
+    #[test]
+    fn test_nested_index() {
+        let matrix = vec![vec![1, 2], vec![3, 4]];
+        let output = ext_format!("$(@{matrix:row}$(@{row:c}[$#index0,$#index1]=$c) *)(\n)*");
+        assert_eq!(output, "[0,0]=1 [0,1]=2\n[1,0]=3 [1,1]=4");
+    }
+
+    #[test]
+    fn test_flat_index() {
+        let grid = vec![
+            vec!["a", "b", "c"],
+            vec!["d", "e", "f"],
+            vec!["g", "h", "i"],
+        ];
+        let output = ext_format!("$(@{grid:row}$(@{row:cell}$#flatindex=$cell) *)(\n)*");
+        assert_eq!(output, "0=a 1=b 2=c\n3=d 4=e 5=f\n6=g 7=h 8=i");
+    }
+
+    #[test]
+    fn test_flat_index_with_uneven_rows() {
+        let rows = vec![vec![1], vec![], vec![2, 3]];
+        let output = ext_format!("$(@{rows:row}[$($row:$#flatindex),*])*");
+        assert_eq!(output, "[1:0][][2:1,3:2]");
+    }
+
+    #[test]
+    fn test_flat_index_in_single_repetition() {
+        let items = vec!["x", "y"];
+        let output = ext_format!("$($#flatindex$items)*");
+        assert_eq!(output, "0x1y");
+    }
+
+    #[test]
+    fn test_count_before_repetition() {
+        let items = vec!["a", "b", "c"];
+        let output = ext_format!("Found $#count items:\n$(@{items:x}$x)(\n)*");
+        assert_eq!(output, "Found 3 items:\na\nb\nc");
+    }
+
+    #[test]
+    fn test_count_in_and_after_repetition() {
+        let items = vec!["a", "b"];
+        let output = ext_format!("$($#index/$#count=$items)(, )* ($#count total)");
+        assert_eq!(output, "0/2=a, 1/2=b (2 total)");
+    }
+
+    #[test]
+    fn test_count_of_multiple_repetitions() {
+        let fruits = vec!["apple", "pear"];
+        let words: Vec<&str> = vec!["a", "bb", "cc"];
+        let output = ext_format!(
+            "$#count fruits: $($fruits) *; $#count lengths: $(group_by(|word: &&str| word.len()): @{words:(len, _)}$len) *"
+        );
+        assert_eq!(output, "2 fruits: apple pear; 2 lengths: 1 2");
+    }
+
+    #[test]
+    fn test_count_in_nested_repetition() {
+        let rows = vec![vec![1, 2, 3], vec![4]];
+        let output = ext_format!("$(@{rows:row}$#count:$($row/$#count),*)(; )*");
+        assert_eq!(output, "2:1/3,2/3,3/3; 2:4/1");
+    }
+
+    #[test]
+    fn test_first_and_last() {
+        let items = vec!["a", "b", "c"];
+        let output = ext_format!("$($items:$#first:$#last)(, )*");
+        assert_eq!(output, "a:true:false, b:false:false, c:false:true");
+        let first = "outside";
+        let output = ext_format!("$first");
+        assert_eq!(output, "outside");
+    }
+
+    #[test]
+    fn test_first_and_last_in_nested_repetition() {
+        let rows = vec![vec![1, 2], vec![3]];
+        let output = ext_format!(
+            "$(@{rows:row}$(if #last: and )[$($(if #first: ^)$row$(if #last: \\$)) *])(, )*"
+        );
+        assert_eq!(output, "[^1 2$], and [^3$]");
+    }
+
+    #[test]
+    fn test_select_clause() {
+        let columns = vec!["id", "name", "email"];
+        let output =
+            ext_format!("$($(if #first: SELECT )$columns$(if #last: \\ FROM users))(, )*;");
+        assert_eq!(output, "SELECT id, name, email FROM users;");
+
+        let columns = vec!["id"];
+        let output =
+            ext_format!("$($(if #first: SELECT )$columns$(if #last: \\ FROM users))(, )*;");
+        assert_eq!(output, "SELECT id FROM users;");
+
+        let columns: Vec<&str> = vec![];
+        let output = ext_format!(
+            "$($(if #first: SELECT )$columns$(if #last: \\ FROM users))(, )*{else:SELECT * FROM users};"
+        );
+        assert_eq!(output, "SELECT * FROM users;");
+    }
+
+    #[test]
+    fn test_count_of_consumed_repetition() {
+        let items = vec![String::from("a"), String::from("b")];
+        let output = ext_format!("$#count: $~($items)*");
+        assert_eq!(output, "2: ab");
+    }
+
+    #[test]
+    fn test_index_outside_of_repetition() {
+        let index = 7;
+        let items = vec!["a", "b"];
+        let output = ext_format!("$index: $($items:$#index),*");
+        assert_eq!(output, "7: a:0,b:1");
+    }
+
+    #[test]
+    fn test_count_outside_of_repetition() {
+        let count = 7;
+        let items = vec!["a", "b"];
+        let output = ext_format!("$count/$#count: $($items:$#count),*");
+        assert_eq!(output, "7/2: a:2,b:2");
+    }
+
+    #[test]
+    fn test_separator_with_sigil() {
+        let v = vec![1, 2, 3];
+        let output = ext_format!("$($v)( $ )*");
+        assert_eq!(output, "1 $ 2 $ 3");
+    }
+
+    #[test]
+    fn test_separator_with_escaped_parenthesis() {
+        let items = vec!["a", "b", "c"];
+        let output = ext_format!(r"$($items)(\) => )*");
+        assert_eq!(output, "a) => b) => c");
+    }
+
+    #[test]
+    fn test_separator_with_escaped_line_break() {
+        let items = vec!["a", "b"];
+        let output = ext_format!(r"$($items)(;\n\t)*");
+        assert_eq!(output, "a;\n\tb");
+        let output = ext_format!(r"$($items)(\\\\)*");
+        assert_eq!(output, "a\\b");
+    }
+
+    #[test]
+    fn test_escaped_backslash_before_letter() {
+        let items = vec!["a", "b"];
+        let output = ext_format!(r"$(\\n$items)(\\t)*");
+        assert_eq!(output, r"\na\t\nb");
+    }
+
+    #[test]
+    fn test_hex_escapes() {
+        let name = "Zoë";
+        let output = ext_format!(r"\x41 caf\xc3\xa9 $name\x7f");
+        assert_eq!(output, "A café Zoë\x7f");
+    }
+
+    #[test]
+    fn test_transpose() {
+        let matrix = vec![vec![1, 2, 3], vec![4, 5, 6]];
+        let output = ext_format!("$(transpose: @{matrix:column}$($column) *)(\n)*");
+        assert_eq!(output, "1 4\n2 5\n3 6");
+        let output = ext_format!("$(@{matrix:row}$($row) *)(\n)*");
+        assert_eq!(output, "1 2 3\n4 5 6");
+    }
+
+    #[test]
+    fn test_transpose_ragged() {
+        let rows = vec![vec!["a", "b", "c"], vec!["d"], vec!["e", "f"]];
+        let output = ext_format!("$(transpose: @{rows:column}$($column),*)(;)*");
+        assert_eq!(output, "a,d,e");
+        let empty: Vec<Vec<i32>> = vec![];
+        let output = ext_format!("$(transpose: @{empty:column}$($column),*)(;)*{else:empty}");
+        assert_eq!(output, "empty");
+    }
+
+    #[test]
+    fn test_checkbox() {
+        let options = vec!["small", "medium", "large"];
+        let selected = 1;
+        let output = ext_format!("$(checkbox(selected): $options)(\n)*");
+        assert_eq!(output, "[ ] small\n[x] medium\n[ ] large");
+    }
+
+    #[test]
+    fn test_radio_with_closure() {
+        let options = vec!["small", "medium", "large"];
+        let choice = "large";
+        let output = ext_format!("$(radio(|option| *option == choice): $options)(\n)*");
+        assert_eq!(output, "( ) small\n( ) medium\n(*) large");
+    }
+
+    #[test]
+    fn test_checkbox_with_sorting() {
+        let sizes = HashMap::from([("small", 1), ("large", 3), ("medium", 2)]);
+        let output = ext_format!(
+            "$(sort_by_value checkbox(|(_, size)| *size > 1): @{sizes:(name, _)}$name)(, )*"
+        );
+        assert_eq!(output, "[ ] small, [x] medium, [x] large");
+    }
+
+    struct Failing;
+
+    impl fmt::Display for Failing {
+        fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+            Err(fmt::Error)
+        }
+    }
+
+    fn render_report(names: &[&str], total: Option<u32>) -> Result<String, fmt::Error> {
+        let report = try_ext_format!("#![on_missing=error]\n$($names)(, )*: ${total?}")?;
+        Ok(report.to_uppercase())
+    }
+
+    #[test]
+    fn test_try_ext_format() {
+        let name = "world";
+        let numbers = vec![1, 2, 3];
+        let output = try_ext_format!("Hello, $name! $($numbers)(, )*");
+        assert_eq!(output, Ok("Hello, world! 1, 2, 3".to_string()));
+
+        let report = render_report(&["a", "b"], Some(3));
+        assert_eq!(report, Ok("A, B: 3".to_string()));
+    }
+
+    #[test]
+    fn test_try_ext_format_with_errors() {
+        let failing = Failing;
+        assert_eq!(try_ext_format!("before $failing after"), Err(fmt::Error));
+
+        assert_eq!(render_report(&["a"], None), Err(fmt::Error));
+
+        let total: Option<u32> = None;
+        assert_eq!(
+            try_ext_format!("total: ${total?}"),
+            Ok("total: ".to_string())
+        );
+    }
 }
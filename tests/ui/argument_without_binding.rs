@@ -0,0 +1,6 @@
+use ext_format::ext_format;
+
+fn main() {
+    let name = "world";
+    let _ = ext_format!("Hello, $name!", name);
+}
@@ -0,0 +1,7 @@
+use ext_format::ext_format;
+
+fn main() {
+    let rows = vec![vec![1]];
+    let columns = vec![2];
+    let _ = ext_format!("$(@{rows:x} $(@{columns:x} $x)*)*");
+}
@@ -0,0 +1,5 @@
+use ext_format::ext_format;
+
+fn main() {
+    let _ = ext_format!();
+}
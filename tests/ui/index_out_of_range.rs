@@ -0,0 +1,6 @@
+use ext_format::ext_format;
+
+fn main() {
+    let items = vec!["a", "b"];
+    let _ = ext_format!("$($items $#index3)*");
+}
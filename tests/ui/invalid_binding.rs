@@ -0,0 +1,7 @@
+use ext_format::ext_format;
+
+fn main() {
+    let _ = ext_format!("$total", total = 1 +);
+    let _ = ext_format!("$total", total = 1 2);
+    let _ = ext_format!("$total", total =, other = 1);
+}
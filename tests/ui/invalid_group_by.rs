@@ -0,0 +1,10 @@
+use ext_format::ext_format;
+
+fn main() {
+    let a = vec![1, 2];
+    let b = vec![3, 4];
+    let _ = ext_format!("$(group_by(|x| *x): $a $b)*");
+    let _ = ext_format!("$~(group_by(|x| *x): $a)*");
+    let _ = ext_format!("$(group_by(|x| x +): $a)*");
+    let _ = ext_format!("$(transpose: $a $b)*");
+}
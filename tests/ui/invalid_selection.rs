@@ -0,0 +1,7 @@
+use ext_format::ext_format;
+
+fn main() {
+    let items = vec![1, 2];
+    let _ = ext_format!("$(checkbox(1 +): $items)*");
+    let _ = ext_format!("$~(radio(|item| item > 1): $items)*");
+}
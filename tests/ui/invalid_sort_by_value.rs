@@ -0,0 +1,8 @@
+use ext_format::ext_format;
+
+fn main() {
+    let a = vec![(1, 2)];
+    let b = vec![3, 4];
+    let _ = ext_format!("$(sort_by_value: $b)*");
+    let _ = ext_format!("$(sort_by_value: @{a:(x, y)}@{b:(z, w)}$x $z)*");
+}
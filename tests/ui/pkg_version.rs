@@ -0,0 +1,6 @@
+use ext_format::{ext_format, ext_format_args};
+
+fn main() {
+    let _ = ext_format!("// generated by v$#pkg_version");
+    let _ = ext_format_args!("v$#pkg_version");
+}
@@ -0,0 +1,9 @@
+use ext_format::ext_format;
+
+fn main() {
+    let items = vec!["a", "b"];
+    let _ = ext_format!("$($items:$#idx)*");
+    let _ = ext_format!("$#first $($items)*");
+    let _ = ext_format!("$#count");
+    let _ = ext_format!("$(@#index $items)*");
+}
@@ -0,0 +1,7 @@
+use ext_format::ext_format;
+
+fn main() {
+    let number = 7;
+    let _ = ext_format!("${number|zeropad=3}");
+    let _ = ext_format!("${number|zeropad}");
+}